@@ -1,29 +1,94 @@
 use super::Command;
+use crate::template::date::parse_date;
+use crate::template::site::SiteConfig;
 use crate::template::Builder;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use clap::Args;
+use glob::Pattern;
 use std::path::PathBuf;
 
-/// Builds the static site from the given source
+/// Arguments shared by all commands building the site
 #[derive(Args)]
-pub struct Build {
+pub struct BuildArgs {
     /// Source directory
     #[arg(short, long, default_value = "src")]
-    source: PathBuf,
+    pub source: PathBuf,
 
     /// Output directory
     #[arg(short, long, default_value = "dist")]
-    output: PathBuf,
+    pub output: PathBuf,
+
+    /// Number of pages rendered in parallel [default: number of CPUs]
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+
+    /// Do not use the build cache, even if configured
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Fail the build if any page uses 'exec', even if allowed in the site
+    /// config
+    #[arg(long)]
+    pub no_exec: bool,
+
+    /// Glob patterns of the commands 'exec' may run, separated by commas,
+    /// replacing the allowed commands of the site config
+    #[arg(long, env = "TEMPLE_EXEC_ALLOW", value_delimiter = ',', value_parser = Pattern::new)]
+    pub exec_allow: Option<Vec<Pattern>>,
+
+    /// Date of the build, as RFC 3339 date and time or Unix timestamp, used
+    /// instead of the current date [default: $SOURCE_DATE_EPOCH]
+    #[arg(long, value_parser = parse_date)]
+    pub date: Option<DateTime<Utc>>,
+
+    /// Replace the output directory even if it contains or is inside the
+    /// source directory or has not been created by temple
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl BuildArgs {
+    /// Loads the site config from the source directory and applies the
+    /// overrides passed via command line arguments.
+    pub fn site_config(&self) -> Result<SiteConfig> {
+        let mut config = SiteConfig::load(&self.source)?;
+        if self.jobs.is_some() {
+            config.jobs = self.jobs;
+        }
+        if self.no_cache {
+            config.cache_dir = None;
+        }
+        if self.no_exec {
+            config.exec.enabled = false;
+        }
+        if self.exec_allow.is_some() {
+            config.exec.allow = self.exec_allow.clone();
+        }
+        if self.date.is_some() {
+            config.date = self.date;
+        }
+        config.force = self.force;
+        Ok(config)
+    }
+}
+
+/// Builds the static site from the given source
+#[derive(Args)]
+pub struct Build {
+    #[command(flatten)]
+    args: BuildArgs,
 }
 
 impl Command for Build {
     fn run(&self) -> Result<()> {
-        let builder = Builder::new(&self.source, &self.output);
+        let config = self.args.site_config()?;
+        let builder = Builder::new(&self.args.source, &self.args.output, config);
 
         log::info!(
             "Building from {:?} into {:?} ...",
-            &self.source,
-            &self.output
+            &self.args.source,
+            &self.args.output
         );
 
         builder.build()?;
@@ -33,3 +98,34 @@ impl Command for Build {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test_site_config {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: BuildArgs,
+    }
+
+    fn allowed(args: &[&str]) -> Option<Vec<String>> {
+        let config = Cli::parse_from(args).args.site_config().unwrap();
+        let allow = config.exec.allow?;
+        Some(allow.iter().map(|p| p.as_str().to_string()).collect())
+    }
+
+    #[test]
+    fn exec_allow() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("temple.toml"), "[exec]\nallow = [\"git\"]\n").unwrap();
+        let source = dir.path().to_str().unwrap();
+
+        assert_eq!(allowed(&["temple", "-s", source]).unwrap(), ["git"]);
+        assert_eq!(
+            allowed(&["temple", "-s", source, "--exec-allow", "echo,scripts/*.sh"]).unwrap(),
+            ["echo", "scripts/*.sh"]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use super::Command;
+use super::{BuildArgs, Command};
 use crate::server::run_dev_server;
 use crate::template::Builder;
 use anyhow::Result;
@@ -12,13 +12,8 @@ use tokio::sync::broadcast;
 /// Watches the given source directory for changes and rebuilds if detected
 #[derive(Args)]
 pub struct Watch {
-    /// Source directory
-    #[arg(short, long, default_value = "src")]
-    source: PathBuf,
-
-    /// Output directory
-    #[arg(short, long, default_value = "dist")]
-    output: PathBuf,
+    #[command(flatten)]
+    args: BuildArgs,
 
     /// Address to bind dev server to
     #[arg(short, long, default_value = "127.0.0.1:8081")]
@@ -31,11 +26,16 @@ pub struct Watch {
 
 impl Command for Watch {
     fn run(&self) -> Result<()> {
-        let builder = Builder::new(&self.source, &self.output);
+        let config = self.args.site_config()?;
 
         let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
         let mut watcher = notify::recommended_watcher(tx)?;
-        watcher.watch(&self.source, notify::RecursiveMode::Recursive)?;
+        watcher.watch(&self.args.source, notify::RecursiveMode::Recursive)?;
+        for theme_dir in outside_theme_dirs(&self.args.source, &config.themes) {
+            watcher.watch(&theme_dir, notify::RecursiveMode::Recursive)?;
+        }
+
+        let builder = Builder::new(&self.args.source, &self.args.output, config);
 
         log::info!("Initial build ...");
         builder.build()?;
@@ -47,7 +47,7 @@ impl Command for Watch {
             log::info!("Running internal dev server on {url}");
 
             let address = self.address.to_owned();
-            let output_path = self.output.to_owned();
+            let output_path = self.args.output.to_owned();
             let tx = tx.clone();
             thread::spawn(move || {
                 let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
@@ -60,7 +60,7 @@ impl Command for Watch {
             }
         }
 
-        watch_handler(&self.source, rx, &tx, &builder);
+        watch_handler(&self.args.source, rx, &tx, &builder);
 
         Ok(())
     }
@@ -83,7 +83,7 @@ fn watch_handler(
                 ) =>
             {
                 log::info!("Change detected {:?}: {:?}", e.kind, e.paths);
-                match builder.build() {
+                match builder.rebuild(&e.paths) {
                     Err(err) => log::error!("build failed: {err}"),
                     Ok(_) => {
                         tx.send(()).ok();
@@ -95,3 +95,42 @@ fn watch_handler(
         }
     }
 }
+
+/// Returns the existing theme directories which are not located within the
+/// source directory and need to be watched separately. Paths are compared
+/// canonicalized, so that e.g. `src/../themes/shared` is not taken as part of
+/// `src`.
+fn outside_theme_dirs(source: &Path, themes: &[PathBuf]) -> Vec<PathBuf> {
+    let Ok(source) = source.canonicalize() else {
+        return vec![];
+    };
+    themes
+        .iter()
+        .filter_map(|theme| source.join(theme).canonicalize().ok())
+        .filter(|dir| dir.is_dir() && !dir.starts_with(&source))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_outside_theme_dirs {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn general() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("src/themes/inner")).unwrap();
+        fs::create_dir_all(root.join("themes/shared")).unwrap();
+
+        let themes = [
+            PathBuf::from("../themes/shared"),
+            PathBuf::from("themes/inner"),
+            PathBuf::from("../themes/missing"),
+        ];
+        assert_eq!(
+            outside_theme_dirs(&root.join("src"), &themes),
+            [root.join("themes/shared")]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use super::errors::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const CACHE_FILE: &str = "cache.toml";
+const OUTPUTS_DIR: &str = "outputs";
+
+/// Contents of the cache file.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+struct CacheData {
+    /// Version of temple which has written the cache.
+    version: String,
+    /// Hash over all input files of the build.
+    inputs: String,
+    /// Hashes of all files in the output directory by their relative path.
+    outputs: BTreeMap<String, String>,
+    /// Rendered pages by their id.
+    pages: BTreeMap<String, CachedPage>,
+    /// Outputs of 'exec' commands by their key.
+    exec: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CachedPage {
+    /// Hash over the page and all of its dependencies.
+    pub key: String,
+    /// Hash of the rendered output.
+    pub output: String,
+    /// Names of all templates used by the page.
+    pub templates: BTreeSet<String>,
+    /// Whether the page lists other pages.
+    pub pages: bool,
+    /// Hashes of the files run or read by 'exec' commands of the page by
+    /// their path.
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+    /// Whether the output of the page can change without its inputs
+    /// changing. The output of such pages is never reused.
+    #[serde(default)]
+    pub volatile: bool,
+}
+
+/// Persistent cache of build results across invocations.
+///
+/// The cache holds the results of the previous build, which are looked up
+/// during the current build, and collects the results of the current build,
+/// which are stored on [`BuildCache::save`]. By that, only entries which are
+/// still in use are kept.
+pub struct BuildCache {
+    dir: PathBuf,
+    previous: CacheData,
+    current: Mutex<CacheData>,
+}
+
+impl BuildCache {
+    /// Loads the cache from the given cache directory. If the cache does not
+    /// exist, can not be read or has been written by another version of
+    /// temple, an empty cache is used.
+    pub fn load(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let previous = match fs::read_to_string(dir.join(CACHE_FILE)) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|err| {
+                log::warn!("Ignoring invalid build cache: {err}");
+                CacheData::default()
+            }),
+            Err(_) => CacheData::default(),
+        };
+
+        let previous = if previous.version == env!("CARGO_PKG_VERSION") {
+            previous
+        } else {
+            CacheData::default()
+        };
+
+        Self {
+            dir,
+            previous,
+            current: Mutex::default(),
+        }
+    }
+
+    /// Returns `true` if the previous build had the same `inputs`, none of its
+    /// pages is volatile or uses a changed file via 'exec' and the given output
+    /// directory still contains exactly its outputs.
+    pub fn is_up_to_date(&self, inputs: &str, target_dir: &Path) -> bool {
+        self.previous.inputs == inputs
+            && self.previous.pages.values().all(|p| {
+                !p.volatile
+                    && p.files
+                        .iter()
+                        .all(|(path, h)| fs::read(path).is_ok_and(|c| &hash([c]) == h))
+            })
+            && hash_dir(target_dir).is_ok_and(|outputs| outputs == self.previous.outputs)
+    }
+
+    /// Returns the page with the given `id` of the previous build.
+    pub fn page(&self, id: &Path) -> Option<&CachedPage> {
+        self.previous.pages.get(&id.to_string_lossy().to_string())
+    }
+
+    /// Returns the rendered output of the page with the given `id` of the
+    /// previous build, if the page had the same `key` and is not volatile.
+    pub fn page_output(&self, id: &Path, key: &str) -> Option<Vec<u8>> {
+        let page = self.page(id).filter(|p| p.key == key && !p.volatile)?;
+        fs::read(self.dir.join(OUTPUTS_DIR).join(&page.output)).ok()
+    }
+
+    /// Stores the rendered `output` of the page with the given `id`, whose
+    /// hash is the `output` of the cached `page`.
+    pub fn store_page(&self, id: &Path, page: CachedPage, output: &[u8]) -> Result<()> {
+        debug_assert_eq!(page.output, hash([output]));
+        let path = self.dir.join(OUTPUTS_DIR).join(&page.output);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().expect("parent dir"))?;
+            fs::write(path, output)?;
+        }
+        self.current
+            .lock()
+            .expect("build cache lock")
+            .pages
+            .insert(id.to_string_lossy().to_string(), page);
+        Ok(())
+    }
+
+    /// Returns the output of the 'exec' command with the given `key`, either
+    /// from the previous or the current build.
+    pub fn exec_output(&self, key: &str) -> Option<String> {
+        let mut current = self.current.lock().expect("build cache lock");
+        if let Some(output) = current.exec.get(key) {
+            return Some(output.clone());
+        }
+        let output = self.previous.exec.get(key)?;
+        current.exec.insert(key.to_string(), output.clone());
+        Some(output.clone())
+    }
+
+    pub fn store_exec_output(&self, key: &str, output: &str) {
+        self.current
+            .lock()
+            .expect("build cache lock")
+            .exec
+            .insert(key.to_string(), output.to_string());
+    }
+
+    /// Writes the results of the current build with the given `inputs` hash
+    /// and the resulting output directory to the cache directory and removes
+    /// all outputs which are not used anymore.
+    pub fn save(self, inputs: String, target_dir: &Path) -> Result<()> {
+        let mut data = self.current.into_inner().expect("build cache lock");
+        data.version = env!("CARGO_PKG_VERSION").to_string();
+        data.inputs = inputs;
+        data.outputs = hash_dir(target_dir)?;
+
+        fs::create_dir_all(&self.dir)?;
+        let content = toml::to_string(&data).expect("serialize build cache");
+        fs::write(self.dir.join(CACHE_FILE), content)?;
+
+        let used: BTreeSet<_> = data.pages.values().map(|p| p.output.as_str()).collect();
+        if let Ok(entries) = fs::read_dir(self.dir.join(OUTPUTS_DIR)) {
+            for entry in entries.flatten() {
+                if !used.contains(entry.file_name().to_string_lossy().as_ref()) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the hex encoded SHA-256 hash over all given `parts`. Each part is
+/// prefixed with its length, so that the boundaries between parts are part of
+/// the hash.
+pub fn hash<T: AsRef<[u8]>>(parts: impl IntoIterator<Item = T>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        let part = part.as_ref();
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns the hashes of all files in `dir` and its subdirectories by their
+/// path relative to `dir`.
+pub fn hash_dir(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    for path in walk_files(dir)? {
+        let rel = path.strip_prefix(dir).expect("path in dir");
+        hashes.insert(rel.to_string_lossy().to_string(), hash([fs::read(&path)?]));
+    }
+    Ok(hashes)
+}
+
+/// Returns the paths of all files in `dir` and its subdirectories in sorted
+/// order. Returns an empty list if `dir` does not exist.
+pub fn walk_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(walk_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test_hash {
+    use super::*;
+
+    #[test]
+    fn general() {
+        assert_eq!(hash(["a"]), hash(["a"]));
+        assert_ne!(hash(["a"]), hash(["b"]));
+        assert_ne!(hash(["ab", "c"]), hash(["a", "bc"]));
+        assert_eq!(
+            hash(Vec::<&str>::new()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_build_cache {
+    use super::*;
+
+    fn page(volatile: bool, output: &[u8]) -> CachedPage {
+        CachedPage {
+            key: "key".into(),
+            output: hash([output]),
+            templates: BTreeSet::new(),
+            pages: false,
+            files: BTreeMap::new(),
+            volatile,
+        }
+    }
+
+    #[test]
+    fn volatile_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_dir = dir.path().join("out");
+        fs::create_dir(&target_dir).unwrap();
+
+        let cache = BuildCache::load(dir.path().join("cache"));
+        let (stable, volatile) = (Path::new("stable.md"), Path::new("volatile.md"));
+        cache.store_page(stable, page(false, b"a"), b"a").unwrap();
+        cache.store_page(volatile, page(true, b"b"), b"b").unwrap();
+        cache.save("inputs".into(), &target_dir).unwrap();
+
+        let cache = BuildCache::load(dir.path().join("cache"));
+        assert_eq!(cache.page_output(stable, "key").as_deref(), Some(&b"a"[..]));
+        assert_eq!(cache.page_output(stable, "other"), None);
+        assert_eq!(cache.page_output(volatile, "key"), None);
+        assert!(!cache.is_up_to_date("inputs", &target_dir));
+
+        cache.store_page(stable, page(false, b"a"), b"a").unwrap();
+        cache.save("inputs".into(), &target_dir).unwrap();
+        assert!(BuildCache::load(dir.path().join("cache")).is_up_to_date("inputs", &target_dir));
+    }
+
+    #[test]
+    fn exec_outputs_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_dir = dir.path().join("out");
+        fs::create_dir(&target_dir).unwrap();
+        let script = dir.path().join("script.sh");
+        fs::write(&script, "echo a").unwrap();
+
+        let cache = BuildCache::load(dir.path().join("cache"));
+        cache.store_exec_output("cmd", "out");
+        let mut cached = page(false, b"a");
+        let path = script.to_string_lossy().to_string();
+        cached.files.insert(path, hash([b"echo a"]));
+        cache
+            .store_page(Path::new("index.md"), cached, b"a")
+            .unwrap();
+        cache.save("inputs".into(), &target_dir).unwrap();
+
+        let cache = BuildCache::load(dir.path().join("cache"));
+        assert_eq!(cache.exec_output("cmd").as_deref(), Some("out"));
+        assert_eq!(cache.exec_output("other"), None);
+        assert!(cache.is_up_to_date("inputs", &target_dir));
+        fs::write(&script, "echo b").unwrap();
+        assert!(!cache.is_up_to_date("inputs", &target_dir));
+    }
+}
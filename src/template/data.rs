@@ -0,0 +1,119 @@
+use super::cache::walk_files;
+use super::errors::{Error, Result};
+use super::value;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// Loads all data files in `dir` and its subdirectories into a single tree,
+/// in which each file is stored under its path relative to `dir` without
+/// extension, e.g. `team/members.json` as `team.members`. Supported are JSON,
+/// YAML, TOML and CSV files. Returns an empty tree if `dir` does not exist.
+pub fn load_data(dir: &Path) -> Result<Value> {
+    let mut root = Map::new();
+
+    for path in walk_files(dir)? {
+        let Some(value) = read_data_file(&path)? else {
+            log::warn!("Ignoring data file {path:?} with unsupported format");
+            continue;
+        };
+
+        let rel = path
+            .strip_prefix(dir)
+            .expect("path in dir")
+            .with_extension("");
+        let keys: Vec<_> = rel.iter().map(|c| c.to_string_lossy()).collect();
+        let (name, parents) = keys.split_last().expect("file name");
+
+        let mut map = &mut root;
+        for (i, key) in parents.iter().enumerate() {
+            let entry = map
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            map = entry
+                .as_object_mut()
+                .ok_or_else(|| Error::DuplicateData(keys[..=i].join(".")))?;
+        }
+        if map.insert(name.to_string(), value).is_some() {
+            return Err(Error::DuplicateData(keys.join(".")));
+        }
+    }
+
+    Ok(Value::Object(root))
+}
+
+/// Reads and parses the data file at `path` by its extension. Returns `None`
+/// if the format is not supported.
+pub fn read_data_file(path: &Path) -> Result<Option<Value>> {
+    let Some(ext) = path.extension().map(|e| e.to_string_lossy().to_lowercase()) else {
+        return Ok(None);
+    };
+    let failed = |err: String| Error::DataParsingFailed(path.to_path_buf(), err);
+
+    let content = fs::read_to_string(path)?;
+    let value = match ext.as_str() {
+        "json" => serde_json::from_str(&content).map_err(|err| failed(err.to_string()))?,
+        "yaml" | "yml" => serde_yaml::from_str(&content).map_err(|err| failed(err.to_string()))?,
+        "toml" => {
+            value::from_toml(toml::from_str(&content).map_err(|err| failed(err.to_string()))?)
+        }
+        "csv" => parse_csv(&content).map_err(|err| failed(err.to_string()))?,
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+/// Parses CSV content with a header row into a list of records, each mapping
+/// the column names to the values of a row.
+fn parse_csv(content: &str) -> csv::Result<Value> {
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut records = vec![];
+    for record in reader.records() {
+        let record: Map<_, _> = headers
+            .iter()
+            .zip(record?.iter())
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect();
+        records.push(Value::Object(record));
+    }
+    Ok(Value::Array(records))
+}
+
+#[cfg(test)]
+mod test_data {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn formats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("team")).unwrap();
+        fs::write(dir.join("site.toml"), "title = \"hey\"").unwrap();
+        fs::write(dir.join("talks.yaml"), "- title: a\n- title: b\n").unwrap();
+        fs::write(dir.join("team/members.json"), r#"[{"name": "x"}]"#).unwrap();
+        fs::write(dir.join("team/roles.csv"), "name,role\nx,dev\ny,ops\n").unwrap();
+
+        assert_eq!(
+            load_data(dir).unwrap(),
+            json!({
+                "site": { "title": "hey" },
+                "talks": [{ "title": "a" }, { "title": "b" }],
+                "team": {
+                    "members": [{ "name": "x" }],
+                    "roles": [
+                        { "name": "x", "role": "dev" },
+                        { "name": "y", "role": "ops" },
+                    ],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn missing_dir() {
+        let data = load_data(Path::new("/this/does/not/exist"));
+        assert_eq!(data.unwrap(), json!({}));
+    }
+}
@@ -0,0 +1,251 @@
+use super::errors::{Error, Result};
+use chrono::{DateTime, FixedOffset, Local, Locale, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Name of the environment variable holding the build date as Unix timestamp,
+/// see <https://reproducible-builds.org/specs/source-date-epoch/>.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Timezone in which dates are formatted.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    /// The local timezone of the system running temple.
+    #[default]
+    Local,
+    /// A timezone of the IANA database, like `Europe/Berlin` or `UTC`.
+    Named(Tz),
+    /// A fixed offset from UTC, like `+02:00`.
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    /// Returns the given date in this timezone.
+    pub fn convert(&self, date: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Self::Local => date.with_timezone(&Local).fixed_offset(),
+            Self::Named(tz) => date.with_timezone(tz).fixed_offset(),
+            Self::Fixed(offset) => date.with_timezone(offset),
+        }
+    }
+
+    /// Returns the given date and time without timezone as date in this
+    /// timezone, or `None` if it does not exist in this timezone.
+    pub fn localize(&self, date: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Self::Local => Local
+                .from_local_datetime(date)
+                .earliest()
+                .map(|d| d.fixed_offset()),
+            Self::Named(tz) => tz
+                .from_local_datetime(date)
+                .earliest()
+                .map(|d| d.fixed_offset()),
+            Self::Fixed(offset) => offset.from_local_datetime(date).earliest(),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Self::Local);
+        }
+        if let Ok(tz) = s.parse::<Tz>() {
+            return Ok(Self::Named(tz));
+        }
+        s.parse::<FixedOffset>()
+            .map(Self::Fixed)
+            .map_err(|_| format!("invalid timezone: {s}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parses a date given either as RFC 3339 date and time, like
+/// `2024-05-01T12:00:00+02:00`, or as Unix timestamp in seconds.
+pub fn parse_date(v: &str) -> std::result::Result<DateTime<Utc>, String> {
+    if let Ok(secs) = v.parse::<i64>() {
+        return Utc
+            .timestamp_opt(secs, 0)
+            .single()
+            .ok_or_else(|| format!("timestamp out of range: {v}"));
+    }
+    DateTime::parse_from_rfc3339(v)
+        .map(|d| d.to_utc())
+        .map_err(|err| format!("invalid date '{v}': {err}"))
+}
+
+/// Parses a date value, e.g. from a page config, given either as RFC 3339 date
+/// and time or as date and optional time without timezone, which are taken as
+/// local date in the given timezone.
+pub fn parse_value_date(v: &str, timezone: &Timezone) -> Option<DateTime<FixedOffset>> {
+    let v = v.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(v) {
+        return Some(date);
+    }
+    let naive = [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|f| NaiveDateTime::parse_from_str(v, f).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(v, "%Y-%m-%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+    })?;
+    timezone.localize(&naive)
+}
+
+/// Returns the date of a variable value, which is either a string parsed with
+/// [`parse_value_date`] or a Unix timestamp in seconds.
+pub fn value_date(v: &serde_json::Value, timezone: &Timezone) -> Option<DateTime<FixedOffset>> {
+    match v {
+        serde_json::Value::String(v) => parse_value_date(v, timezone),
+        serde_json::Value::Number(v) => {
+            let date = Utc.timestamp_opt(v.as_i64()?, 0).single()?;
+            Some(timezone.convert(date))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the date of the last commit changing the file at `path`, or `None`
+/// if the file has not been committed or git is not available.
+pub fn git_last_modified(path: &Path) -> Option<DateTime<Utc>> {
+    let res = Command::new("git")
+        .args(["log", "-1", "--format=%cI", "--"])
+        .arg(path.file_name()?)
+        .current_dir(path.parent()?)
+        .output()
+        .ok()?;
+    if !res.status.success() {
+        return None;
+    }
+    let date = String::from_utf8_lossy(&res.stdout);
+    DateTime::parse_from_rfc3339(date.trim())
+        .ok()
+        .map(|d| d.to_utc())
+}
+
+/// Returns the modification time of the file at `path`.
+pub fn file_modified(path: &Path) -> Result<DateTime<Utc>> {
+    Ok(std::fs::metadata(path)?.modified()?.into())
+}
+
+/// Formats the given date with the strftime like `format`. Names of months and
+/// weekdays are translated to the given `locale`, if any.
+pub fn format_date(
+    date: &DateTime<FixedOffset>,
+    format: &str,
+    locale: Option<Locale>,
+) -> Result<String> {
+    let mut res = String::new();
+    let written = match locale {
+        Some(locale) => write!(res, "{}", date.format_localized(format, locale)),
+        None => write!(res, "{}", date.format(format)),
+    };
+    written.map_err(|_| Error::InvalidDateFormat(format.to_string()))?;
+    Ok(res)
+}
+
+/// Returns the build date given via the `SOURCE_DATE_EPOCH` environment
+/// variable, if set.
+pub fn source_date_epoch() -> Result<Option<DateTime<Utc>>> {
+    let Ok(v) = std::env::var(SOURCE_DATE_EPOCH) else {
+        return Ok(None);
+    };
+    let date = v
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .ok_or(Error::InvalidSourceDateEpoch(v))?;
+    Ok(Some(date))
+}
+
+#[cfg(test)]
+mod test_date {
+    use super::*;
+
+    #[test]
+    fn timezone() {
+        assert_eq!("local".parse(), Ok(Timezone::Local));
+        assert_eq!("UTC".parse(), Ok(Timezone::Named(Tz::UTC)));
+        assert_eq!(
+            "Europe/Berlin".parse(),
+            Ok(Timezone::Named(Tz::Europe__Berlin))
+        );
+        assert_eq!(
+            "+02:00".parse(),
+            Ok(Timezone::Fixed(FixedOffset::east_opt(7200).unwrap()))
+        );
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+
+        let date = parse_date("2024-07-01T12:00:00Z").unwrap();
+        let berlin = Timezone::Named(Tz::Europe__Berlin).convert(date);
+        assert_eq!(berlin.to_rfc3339(), "2024-07-01T14:00:00+02:00");
+    }
+
+    #[test]
+    fn value_dates() {
+        let tz = Timezone::Fixed(FixedOffset::east_opt(3600).unwrap());
+        let parse = |v| parse_value_date(v, &tz).map(|d| d.to_rfc3339());
+        assert_eq!(
+            parse("2024-05-01"),
+            Some("2024-05-01T00:00:00+01:00".into())
+        );
+        assert_eq!(
+            parse("2024-05-01 10:30"),
+            Some("2024-05-01T10:30:00+01:00".into())
+        );
+        assert_eq!(
+            parse("2024-05-01T10:30:00"),
+            Some("2024-05-01T10:30:00+01:00".into())
+        );
+        assert_eq!(
+            parse("2024-05-01T10:30:00Z"),
+            Some("2024-05-01T10:30:00+00:00".into())
+        );
+        assert_eq!(parse("May 1st"), None);
+    }
+
+    #[test]
+    fn formatting() {
+        let date = DateTime::parse_from_rfc3339("2024-03-01T12:00:00+01:00").unwrap();
+        assert_eq!(
+            format_date(&date, "%d %B %Y", None).unwrap(),
+            "01 March 2024"
+        );
+        assert_eq!(
+            format_date(&date, "%d %B %Y", Some(Locale::de_DE)).unwrap(),
+            "01 März 2024"
+        );
+        assert!(matches!(
+            format_date(&date, "%Q", None),
+            Err(Error::InvalidDateFormat(f)) if f == "%Q"
+        ));
+    }
+
+    #[test]
+    fn parsing() {
+        let expected = Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap();
+        assert_eq!(parse_date("1714557600"), Ok(expected));
+        assert_eq!(parse_date("2024-05-01T12:00:00+02:00"), Ok(expected));
+        assert!(parse_date("yesterday").is_err());
+    }
+}
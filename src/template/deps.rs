@@ -0,0 +1,139 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Inputs a rendered page depends on besides its own source file.
+#[derive(Debug, Default)]
+pub struct Dependencies {
+    /// Names of all used and extended templates.
+    pub templates: HashSet<String>,
+    /// Whether the page lists other pages, e.g. via `navitems`, and must be
+    /// re-rendered when pages are added, removed or renamed.
+    pub pages: bool,
+    /// Hashes of the files run or read by 'exec' commands by their path.
+    pub files: BTreeMap<String, String>,
+    /// Whether the page runs 'exec' commands with `nocache` or shows the
+    /// current date, so that its output can change without any of its inputs
+    /// changing.
+    pub volatile: bool,
+}
+
+/// A page rendered in a previous build.
+#[derive(Debug)]
+pub struct RenderedPage {
+    pub output: PathBuf,
+    pub deps: Dependencies,
+}
+
+/// A change of a file in one of the source directories.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The template with the given name has changed.
+    Template(String),
+    /// Multiple templates might have changed, e.g. because a whole templates
+    /// directory was moved.
+    Templates,
+    /// The public file at the given path relative to the public directory has
+    /// changed.
+    Public(PathBuf),
+    /// A page has changed.
+    Page,
+    /// A data file has changed.
+    Data,
+    /// A file in the page bundle with the given directory relative to the
+    /// pages directory has changed.
+    Bundle(PathBuf),
+    /// Any other file has changed, which requires a full build.
+    Other,
+}
+
+/// Classifies the changed `path` by the directory it is located in. All
+/// passed paths must be canonicalized.
+pub fn classify_change(
+    path: &Path,
+    pages_dir: &Path,
+    templates_dirs: &[PathBuf],
+    public_dirs: &[PathBuf],
+    data_dir: &Path,
+) -> Change {
+    if let Ok(rel) = path.strip_prefix(pages_dir) {
+        let mut components = rel.components();
+        return match (components.next(), components.next()) {
+            (Some(bundle), Some(_)) => Change::Bundle(bundle.as_os_str().into()),
+            _ => Change::Page,
+        };
+    }
+
+    if let Some(rel) = templates_dirs
+        .iter()
+        .find_map(|d| path.strip_prefix(d).ok())
+    {
+        if rel.extension().is_none_or(|e| e != "html") {
+            return Change::Templates;
+        }
+        let name = rel.with_extension("");
+        let name: Vec<_> = name.iter().map(|c| c.to_string_lossy()).collect();
+        return Change::Template(name.join("/"));
+    }
+
+    if path.starts_with(data_dir) {
+        return Change::Data;
+    }
+
+    if let Some(rel) = public_dirs.iter().find_map(|d| path.strip_prefix(d).ok()) {
+        if rel.as_os_str().is_empty() {
+            return Change::Other;
+        }
+        return Change::Public(rel.to_path_buf());
+    }
+
+    Change::Other
+}
+
+#[cfg(test)]
+mod test_classify_change {
+    use super::*;
+
+    #[test]
+    fn general() {
+        let pages_dir = Path::new("/src/pages");
+        let templates_dirs = [
+            PathBuf::from("/src/templates"),
+            PathBuf::from("/theme/templates"),
+        ];
+        let public_dirs = [PathBuf::from("/src/public"), PathBuf::from("/theme/public")];
+        let data_dir = Path::new("/src/data");
+        let classify = |path: &str| {
+            classify_change(
+                Path::new(path),
+                pages_dir,
+                &templates_dirs,
+                &public_dirs,
+                data_dir,
+            )
+        };
+
+        assert_eq!(classify("/src/pages/0_index.html"), Change::Page);
+        assert_eq!(classify("/src/pages/post"), Change::Page);
+        assert_eq!(
+            classify("/src/pages/post/img/a.png"),
+            Change::Bundle("post".into())
+        );
+        assert_eq!(
+            classify("/src/templates/base.html"),
+            Change::Template("base".into())
+        );
+        assert_eq!(
+            classify("/theme/templates/components/card.html"),
+            Change::Template("components/card".into())
+        );
+        assert_eq!(classify("/src/templates/components"), Change::Templates);
+        assert_eq!(
+            classify("/theme/public/css/style.css"),
+            Change::Public("css/style.css".into())
+        );
+        assert_eq!(classify("/src/public"), Change::Other);
+        assert_eq!(classify("/src/data/team/members.json"), Change::Data);
+        assert_eq!(classify("/src/data"), Change::Data);
+        assert_eq!(classify("/src/temple.toml"), Change::Other);
+    }
+}
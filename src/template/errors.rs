@@ -1,4 +1,6 @@
+use std::path::PathBuf;
 use std::process::ExitStatus;
+use std::time::Duration;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -13,9 +15,18 @@ pub enum Error {
     #[error("missing argument: {0}")]
     MissingArgument(&'static str),
 
+    #[error("unexpected argument: {0}")]
+    UnexpectedArgument(String),
+
     #[error("unknown template name: {0}")]
     UnknownTemplate(String),
 
+    #[error("invalid template name: {0}")]
+    InvalidTemplateName(String),
+
+    #[error("template not found: {0}")]
+    TemplateNotFound(String),
+
     #[error("unclodes template")]
     UnclosedTemplate,
 
@@ -34,6 +45,87 @@ pub enum Error {
     #[error("'exec' command failed ({0}): {1}")]
     ExecCommandFailed(ExitStatus, String),
 
+    #[error("'exec' command '{0}' timed out after {1:?}")]
+    ExecTimeout(String, Duration),
+
+    #[error("page '{0}' runs the command '{1}' which is not allowed by the exec policy")]
+    ExecNotAllowed(String, String),
+
+    #[error("invalid 'exec' option: {0}")]
+    InvalidExecOption(String),
+
+    #[error("'{0}' without an opening template")]
+    UnmatchedBlockEnd(&'static str),
+
+    #[error("'{0}' without a closing template")]
+    UnclosedBlock(&'static str),
+
+    #[error("unknown filter: {0}")]
+    UnknownFilter(String),
+
+    #[error("invalid argument of filter '{0}': {1}")]
+    InvalidFilterArgument(&'static str, String),
+
+    #[error("invalid heading level: {0}")]
+    InvalidHeadingLevel(String),
+
+    #[error("variable '{0}' can not be iterated")]
+    NotIterable(String),
+
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("page bundle has no index page: {0}")]
+    MissingBundleIndex(PathBuf),
+
+    #[error("invalid output path: {0}")]
+    InvalidOutputPath(String),
+
+    #[error("pages can only be generated from data files in the data directory: {0}")]
+    InvalidGenerateFrom(String),
+
+    #[error("failed parsing data file {0:?}: {1}")]
+    DataParsingFailed(PathBuf, String),
+
+    #[error("data '{0}' is defined by multiple files")]
+    DuplicateData(String),
+
+    #[error("'body' template can only be used in shortcodes")]
+    BodyOutsideShortcode,
+
     #[error("'pagecontent' template can not be at a toplevel page file")]
     ToplevelPageContent,
+
+    #[error("template cycle detected: {0}")]
+    TemplateCycle(String),
+
+    #[error("maximum template depth of {0} exceeded: {1}")]
+    MaxDepthExceeded(usize, String),
+
+    #[error("theme directory not found: {0:?}")]
+    ThemeNotFound(PathBuf),
+
+    #[error("pages '{1}' and '{2}' have the same output path {0:?}")]
+    DuplicateOutput(PathBuf, String, String),
+
+    #[error("invalid output directory: {0:?}")]
+    InvalidOutputDir(PathBuf),
+
+    #[error("refusing to replace output directory {0:?} because {1} (use --force to override)")]
+    UnsafeOutputDir(PathBuf, String),
+
+    #[error("undefined variable: {0}")]
+    UndefinedVariable(String),
+
+    #[error("variable '{0}' is not a valid date: {1}")]
+    InvalidDate(String, String),
+
+    #[error("invalid date format: {0}")]
+    InvalidDateFormat(String),
+
+    #[error("invalid SOURCE_DATE_EPOCH: {0}")]
+    InvalidSourceDateEpoch(String),
+
+    #[error("failed loading site config: {0}")]
+    SiteConfigLoadingFailed(#[from] Box<figment::Error>),
 }
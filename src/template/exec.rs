@@ -0,0 +1,382 @@
+use super::cache;
+use super::errors::{Error, Result};
+use glob::{MatchOptions, Pattern};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval in which a command with a timeout is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Options for matching commands against the patterns of an [`ExecPolicy`].
+/// Wildcards do not match path separators or leading dots, so that e.g.
+/// `scripts/*` does not allow `scripts/../danger`.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+/// Decides which commands 'exec' templates may run.
+#[derive(Debug)]
+pub struct ExecPolicy {
+    pub enabled: bool,
+    /// Patterns of allowed commands. All commands are allowed when `None`.
+    pub allow: Option<Vec<Pattern>>,
+}
+
+impl ExecPolicy {
+    /// Returns whether `command`, as written in the template, may be run.
+    pub fn allows(&self, command: &str) -> bool {
+        self.enabled
+            && self.allow.as_ref().is_none_or(|patterns| {
+                patterns
+                    .iter()
+                    .any(|p| p.matches_with(command, MATCH_OPTIONS))
+            })
+    }
+}
+
+/// A command executed by an 'exec' template.
+#[derive(Debug)]
+pub struct Exec<'a> {
+    pub command: &'a str,
+    pub args: &'a [&'a str],
+    /// Working directory of the command.
+    pub cwd: &'a Path,
+    /// Environment variables passed to the command in addition to the
+    /// environment of temple.
+    pub env: Vec<(String, String)>,
+    /// Environment variables describing the current page, which are passed
+    /// to the command after `env`.
+    pub page_env: Vec<(String, String)>,
+    /// Whether the output of the command depends on the page, so that
+    /// `page_env` is part of the cache key.
+    pub perpage: bool,
+    /// Duration after which the command is killed and the build fails.
+    pub timeout: Option<Duration>,
+    /// Input written to the command's stdin. The command gets no input when
+    /// `None`.
+    pub stdin: Option<&'a str>,
+}
+
+impl Exec<'_> {
+    /// Runs the command and returns its output.
+    pub fn run(&self) -> Result<String> {
+        let mut child = Command::new(self.command)
+            .args(self.args)
+            .current_dir(self.cwd)
+            .envs(self.env.iter().chain(&self.page_env).map(|(k, v)| (k, v)))
+            .stdin(if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Input and outputs are written and read in separate threads, so that
+        // neither the command nor temple block on full pipes.
+        if let Some(input) = self.stdin {
+            let mut pipe = child.stdin.take().expect("piped stdin");
+            let input = input.to_string();
+            thread::spawn(move || {
+                // The command may exit without reading all of its input.
+                pipe.write_all(input.as_bytes()).ok();
+            });
+        }
+        let stdout = read_in_background(child.stdout.take().expect("piped stdout"));
+        let stderr = read_in_background(child.stderr.take().expect("piped stderr"));
+
+        let status = match self.timeout {
+            None => child.wait()?,
+            Some(timeout) => {
+                let start = Instant::now();
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        break status;
+                    }
+                    if start.elapsed() >= timeout {
+                        child.kill()?;
+                        child.wait()?;
+                        return Err(Error::ExecTimeout(self.command.to_string(), timeout));
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        };
+
+        let stdout = stdout.join().expect("stdout reader thread panicked")?;
+        let stderr = stderr.join().expect("stderr reader thread panicked")?;
+
+        if !status.success() {
+            return Err(Error::ExecCommandFailed(
+                status,
+                String::from_utf8_lossy(&stderr).to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&stdout).to_string())
+    }
+
+    /// Returns the paths of the files in the working directory the command or
+    /// its arguments refer to.
+    pub fn files(&self) -> Vec<PathBuf> {
+        [&self.command]
+            .into_iter()
+            .chain(self.args)
+            .map(|arg| self.cwd.join(arg))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Returns the key of the command in the build cache. Besides the command,
+    /// its arguments, working directory, configured environment and input, the
+    /// key covers the contents of all files the command or its arguments refer
+    /// to, so that e.g. changing a script invalidates the cached output of the
+    /// command running it. The page environment is only covered for `perpage`
+    /// commands, so that the output of other commands is shared between pages.
+    pub fn cache_key(&self) -> String {
+        let mut parts = vec![self.cwd.to_string_lossy().as_bytes().to_vec()];
+        for arg in [&self.command].into_iter().chain(self.args) {
+            parts.push(arg.as_bytes().to_vec());
+            let path = self.cwd.join(arg);
+            if path.is_file() {
+                parts.push(fs::read(path).unwrap_or_default());
+            }
+        }
+        let page_env = if self.perpage { &self.page_env[..] } else { &[] };
+        for (k, v) in self.env.iter().chain(page_env) {
+            parts.push(format!("{k}={v}").into_bytes());
+        }
+        if let Some(input) = self.stdin {
+            parts.push(input.as_bytes().to_vec());
+        }
+        cache::hash(parts)
+    }
+}
+
+/// Outputs of the commands run during a build by their cache key, so that
+/// identical commands used on multiple pages run only once.
+#[derive(Default)]
+pub struct ExecMemo {
+    entries: Mutex<HashMap<String, Arc<Mutex<Option<String>>>>>,
+}
+
+impl ExecMemo {
+    /// Returns the memoized output for `key` or calls `run` to produce it.
+    /// Concurrent calls with the same key wait for the first one instead of
+    /// running the command again. Failures are not memoized.
+    pub fn get_or_run(&self, key: &str, run: impl FnOnce() -> Result<String>) -> Result<String> {
+        let entry = self
+            .entries
+            .lock()
+            .expect("exec memo lock")
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+
+        let mut output = entry.lock().expect("exec memo entry lock");
+        if let Some(output) = output.as_ref() {
+            return Ok(output.clone());
+        }
+        let res = run()?;
+        *output = Some(res.clone());
+        Ok(res)
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().expect("exec memo lock").clear();
+    }
+}
+
+fn read_in_background(
+    mut reader: impl Read + Send + 'static,
+) -> thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Returns the name of the environment variable under which the page config
+/// value with the given `key` is passed to commands, e.g. `TEMPLE_CONFIG_TITLE`
+/// for `title`.
+pub fn config_env_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("TEMPLE_CONFIG_{key}")
+}
+
+/// Parses a duration given in seconds, like `10` or `0.5`.
+pub fn parse_timeout(v: &str) -> Option<Duration> {
+    v.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+#[cfg(test)]
+mod test_exec {
+    use super::*;
+
+    fn exec<'a>(command: &'a str, args: &'a [&'a str]) -> Exec<'a> {
+        Exec {
+            command,
+            args,
+            cwd: Path::new("."),
+            env: vec![],
+            page_env: vec![],
+            perpage: false,
+            timeout: None,
+            stdin: None,
+        }
+    }
+
+    #[test]
+    fn run() {
+        let res = exec("echo", &["hello"]).run();
+        assert_eq!(res.unwrap(), "hello\n");
+
+        let mut cmd = exec("sh", &["-c", "echo $FOO"]);
+        cmd.env = vec![("FOO".into(), "bar".into())];
+        assert_eq!(cmd.run().unwrap(), "bar\n");
+
+        assert!(matches!(
+            exec("sh", &["-c", "exit 1"]).run(),
+            Err(Error::ExecCommandFailed(_, _))
+        ));
+    }
+
+    #[test]
+    fn stdin() {
+        let mut cmd = exec("tr", &["a-z", "A-Z"]);
+        cmd.stdin = Some("hello");
+        assert_eq!(cmd.run().unwrap(), "HELLO");
+
+        // Large inputs must not block on full pipes.
+        let input = "x".repeat(1 << 20);
+        let mut cmd = exec("cat", &[]);
+        cmd.stdin = Some(&input);
+        assert_eq!(cmd.run().unwrap().len(), input.len());
+
+        // Commands may ignore their input.
+        let mut cmd = exec("echo", &["ignored"]);
+        cmd.stdin = Some(&input);
+        assert_eq!(cmd.run().unwrap(), "ignored\n");
+    }
+
+    #[test]
+    fn timeout() {
+        let mut cmd = exec("sh", &["-c", "echo fast"]);
+        cmd.timeout = Some(Duration::from_secs(10));
+        assert_eq!(cmd.run().unwrap(), "fast\n");
+
+        let mut cmd = exec("sleep", &["10"]);
+        cmd.timeout = Some(Duration::from_millis(50));
+        assert!(matches!(cmd.run(), Err(Error::ExecTimeout(c, _)) if c == "sleep"));
+    }
+
+    #[test]
+    fn cache_key() {
+        let page = |name: &str, perpage| {
+            let mut cmd = exec("sh", &["-c", "echo $TEMPLE_PAGE_NAME"]);
+            cmd.page_env = vec![("TEMPLE_PAGE_NAME".into(), name.into())];
+            cmd.perpage = perpage;
+            cmd
+        };
+        assert_eq!(page("a", false).cache_key(), page("b", false).cache_key());
+        assert_eq!(page("a", true).cache_key(), page("a", true).cache_key());
+        assert_ne!(page("a", true).cache_key(), page("b", true).cache_key());
+        assert_eq!(page("a", false).run().unwrap(), "a\n");
+
+        let mut cmd = exec("sh", &["-c", "echo $FOO"]);
+        cmd.env = vec![("FOO".into(), "a".into())];
+        assert_ne!(
+            cmd.cache_key(),
+            exec("sh", &["-c", "echo $FOO"]).cache_key()
+        );
+    }
+
+    #[test]
+    fn memo() {
+        let memo = ExecMemo::default();
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let run = || {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            Ok("out".to_string())
+        };
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| assert_eq!(memo.get_or_run("a", run).unwrap(), "out"));
+            }
+        });
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        assert_eq!(memo.get_or_run("b", run).unwrap(), "out");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        assert!(memo
+            .get_or_run("c", || Err(Error::InvalidExecOption("x".into())))
+            .is_err());
+        assert_eq!(memo.get_or_run("c", run).unwrap(), "out");
+
+        memo.clear();
+        assert_eq!(memo.get_or_run("a", run).unwrap(), "out");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn policy() {
+        let patterns = |v: &[&str]| v.iter().map(|p| Pattern::new(p).unwrap()).collect();
+
+        let policy = ExecPolicy {
+            enabled: true,
+            allow: None,
+        };
+        assert!(policy.allows("anything"));
+
+        let policy = ExecPolicy {
+            enabled: false,
+            allow: None,
+        };
+        assert!(!policy.allows("anything"));
+
+        let policy = ExecPolicy {
+            enabled: true,
+            allow: Some(patterns(&["git", "scripts/*.sh"])),
+        };
+        assert!(policy.allows("git"));
+        assert!(policy.allows("scripts/build.sh"));
+        assert!(!policy.allows("gitk"));
+        assert!(!policy.allows("rm"));
+        assert!(!policy.allows("scripts/sub/build.sh"));
+        assert!(!policy.allows("scripts/../build.sh"));
+        assert!(!policy.allows("/bin/scripts/build.sh"));
+
+        let policy = ExecPolicy {
+            enabled: false,
+            allow: Some(patterns(&["git"])),
+        };
+        assert!(!policy.allows("git"));
+    }
+
+    #[test]
+    fn env_name() {
+        assert_eq!(config_env_name("title"), "TEMPLE_CONFIG_TITLE");
+        assert_eq!(config_env_name("some-key.x"), "TEMPLE_CONFIG_SOME_KEY_X");
+    }
+
+    #[test]
+    fn timeout_parsing() {
+        assert_eq!(parse_timeout("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_timeout("0.5"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_timeout("-1"), None);
+        assert_eq!(parse_timeout("abc"), None);
+    }
+}
@@ -0,0 +1,553 @@
+use super::date::{self, Timezone};
+use super::errors::{Error, Result};
+use super::parsing::Filter;
+use super::value;
+use chrono::Locale;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Schemes which may be used in URLs inserted with the `url` filter. URLs with
+/// other schemes, like `javascript:`, are replaced by `#`.
+const SAFE_URL_SCHEMES: &[&str] = &["http", "https", "mailto", "tel", "ftp"];
+
+/// Characters besides ASCII letters and digits which are not percent encoded
+/// by the `url` filter.
+const URL_CHARS: &[u8] = b"-._~:/?#[]@!$&'()*+,;=%";
+
+/// Characters besides ASCII letters and digits which are not percent encoded
+/// by the `urlencode` filter.
+const URL_COMPONENT_CHARS: &[u8] = b"-._~";
+
+/// Filters available in all templates.
+const BUILTIN_FILTERS: &[(&str, FilterFn)] = &[
+    ("safe", safe),
+    ("raw", safe),
+    ("escape", escape),
+    ("attr", attr),
+    ("url", url),
+    ("urlencode", urlencode),
+    ("upper", upper),
+    ("lower", lower),
+    ("trim", trim),
+    ("replace", replace),
+    ("slugify", slugify),
+    ("truncate", truncate),
+    ("default", default),
+    ("date", date),
+    ("json", json),
+    ("markdown", markdown),
+];
+
+/// A value passed through the filters of a variable.
+#[derive(Debug)]
+pub struct Filtered {
+    pub value: Value,
+    /// Whether the value is inserted without HTML escaping.
+    pub safe: bool,
+}
+
+/// Information available to filters besides their arguments.
+pub struct FilterContext<'a> {
+    /// Path of the variable or command whose value the filters are applied
+    /// to.
+    pub path: &'a str,
+    pub timezone: &'a Timezone,
+    pub locale: Option<Locale>,
+}
+
+/// A filter, which transforms the value passed to it with the given
+/// arguments.
+pub type FilterFn = fn(Filtered, &[&str], &FilterContext) -> Result<Filtered>;
+
+/// Registry of the filters which can be used in templates, like
+/// `{{ page.title | upper }}`.
+pub struct Filters {
+    filters: HashMap<&'static str, FilterFn>,
+}
+
+impl Filters {
+    /// Registers `filter` under `name`, replacing any filter with this name.
+    pub fn register(&mut self, name: &'static str, filter: FilterFn) {
+        self.filters.insert(name, filter);
+    }
+
+    /// Returns the text inserted for a variable with the given value after
+    /// applying `filters` to it. The text is HTML escaped unless a filter
+    /// marks it as safe, like `safe` or one of the escaping filters.
+    pub fn apply(&self, value: Value, filters: &[Filter], ctx: &FilterContext) -> Result<String> {
+        let mut res = Filtered { value, safe: false };
+        for filter in filters {
+            let apply = self
+                .filters
+                .get(filter.name)
+                .ok_or_else(|| Error::UnknownFilter(filter.name.to_string()))?;
+            res = apply(res, &filter.args, ctx)?;
+        }
+
+        let text = value::to_string(&res.value);
+        Ok(if res.safe { text } else { escape_html(&text) })
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        let mut filters = Self {
+            filters: HashMap::new(),
+        };
+        for (name, filter) in BUILTIN_FILTERS {
+            filters.register(name, *filter);
+        }
+        filters
+    }
+}
+
+/// Fails unless the number of `args` is within `min` and `max`.
+fn expect_args(args: &[&str], min: usize, max: usize) -> Result<()> {
+    if args.len() < min {
+        return Err(Error::MissingArgument("filter argument"));
+    }
+    if let Some(arg) = args.get(max) {
+        return Err(Error::UnexpectedArgument(arg.to_string()));
+    }
+    Ok(())
+}
+
+/// Replaces the value by the result of `map` applied to its text.
+fn map_text(f: Filtered, map: impl FnOnce(String) -> String) -> Filtered {
+    Filtered {
+        value: Value::String(map(value::to_string(&f.value))),
+        safe: f.safe,
+    }
+}
+
+/// Replaces the value by `text`, which is inserted without escaping.
+fn safe_text(text: String) -> Filtered {
+    Filtered {
+        value: Value::String(text),
+        safe: true,
+    }
+}
+
+fn safe(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(Filtered { safe: true, ..f })
+}
+
+fn escape(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    if f.safe {
+        return Ok(f);
+    }
+    Ok(safe_text(escape_html(&value::to_string(&f.value))))
+}
+
+fn attr(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(safe_text(escape_attr(&value::to_string(&f.value))))
+}
+
+fn url(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    let url = sanitize_url(&value::to_string(&f.value));
+    Ok(safe_text(escape_html(&url)))
+}
+
+fn urlencode(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(map_text(f, |text| {
+        percent_encode(&text, URL_COMPONENT_CHARS)
+    }))
+}
+
+fn upper(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(map_text(f, |text| text.to_uppercase()))
+}
+
+fn lower(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(map_text(f, |text| text.to_lowercase()))
+}
+
+fn trim(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(map_text(f, |text| text.trim().to_string()))
+}
+
+fn replace(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 2, 2)?;
+    Ok(map_text(f, |text| text.replace(args[0], args[1])))
+}
+
+fn slugify(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    Ok(map_text(f, |text| to_slug(&text)))
+}
+
+/// Shortens the value to the given number of characters and appends `…`, or
+/// the optional second argument, if it was shortened.
+fn truncate(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 1, 2)?;
+    let len: usize = args[0]
+        .parse()
+        .map_err(|_| Error::InvalidFilterArgument("truncate", args[0].to_string()))?;
+    let end = args.get(1).copied().unwrap_or("…");
+    Ok(map_text(f, |text| match text.char_indices().nth(len) {
+        Some((i, _)) => text[..i].trim_end().to_string() + end,
+        None => text,
+    }))
+}
+
+/// Replaces null and empty values by the given argument.
+fn default(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 1, 1)?;
+    let is_empty = match &f.value {
+        Value::Null => true,
+        Value::String(v) => v.is_empty(),
+        _ => false,
+    };
+    if !is_empty {
+        return Ok(f);
+    }
+    Ok(Filtered {
+        value: Value::String(args[0].to_string()),
+        safe: false,
+    })
+}
+
+fn date(f: Filtered, args: &[&str], ctx: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 1)?;
+    let date = date::value_date(&f.value, ctx.timezone)
+        .ok_or_else(|| Error::InvalidDate(ctx.path.to_string(), f.value.to_string()))?;
+    let format = args.first().copied().unwrap_or("%Y-%m-%d");
+    let text = date::format_date(&date, format, ctx.locale)?;
+    Ok(map_text(f, |_| text))
+}
+
+/// Inserts the value as JSON. `<`, `>` and `&` are written as unicode escapes,
+/// so that the JSON can be used within `<script>` elements with `safe`.
+fn json(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    let json = f
+        .value
+        .to_string()
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026");
+    Ok(map_text(f, |_| json))
+}
+
+/// Renders the value as Markdown. HTML within the value is escaped, link and
+/// image targets are sanitized like by `url`, and a single paragraph is not
+/// wrapped in `<p>`, so that the value can be used inline, e.g. in headings.
+fn markdown(f: Filtered, args: &[&str], _: &FilterContext) -> Result<Filtered> {
+    expect_args(args, 0, 0)?;
+    let text = value::to_string(&f.value);
+    let events = Parser::new_ext(&text, Options::ENABLE_STRIKETHROUGH).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: sanitize_url(&dest_url).into(),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: sanitize_url(&dest_url).into(),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+
+    let html = html.trim_end();
+    let html = html
+        .strip_prefix("<p>")
+        .and_then(|h| h.strip_suffix("</p>"))
+        .filter(|h| !h.contains("<p>"))
+        .unwrap_or(html);
+    Ok(safe_text(html.to_string()))
+}
+
+/// Escapes the characters with special meaning in HTML text and quoted
+/// attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Escapes `text` for attribute values, including unquoted ones, by encoding
+/// all ASCII characters except letters, digits and `,.-_` as character
+/// references.
+pub fn escape_attr(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() || !c.is_ascii() => res.push(c),
+            ',' | '.' | '-' | '_' => res.push(c),
+            c => res.push_str(&format!("&#x{:02X};", c as u32)),
+        }
+    }
+    res
+}
+
+/// Makes `url` safe to be used as link target: Characters which are not
+/// allowed in URLs are percent encoded and URLs with a scheme which could run
+/// code, like `javascript:`, are replaced by `#`.
+pub fn sanitize_url(url: &str) -> String {
+    let url = url.trim();
+    if let Some((scheme, _)) = url.split_once(':') {
+        let is_scheme = !scheme.contains(['/', '?', '#']);
+        if is_scheme && !SAFE_URL_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) {
+            return "#".to_string();
+        }
+    }
+    percent_encode(url, URL_CHARS)
+}
+
+/// Percent encodes all bytes of `text` except ASCII letters, digits and the
+/// given `keep` characters.
+fn percent_encode(text: &str, keep: &[u8]) -> String {
+    let mut res = String::with_capacity(text.len());
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || keep.contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{b:02X}"));
+        }
+    }
+    res
+}
+
+/// Returns the slug of `text` for use in URLs and ids: Its letters and digits
+/// in lowercase, with all other characters in between replaced by `-`, e.g.
+/// `hello-world` for `Hello, World!`.
+pub fn to_slug(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+#[cfg(test)]
+mod test_filters {
+    use super::*;
+    use serde_json::json;
+
+    /// Applies the filters given like in a template, e.g. `trim | upper`.
+    fn apply(value: Value, expr: &str) -> Result<String> {
+        let filters: Vec<_> = expr
+            .split('|')
+            .map(|f| {
+                let mut split = f.split_whitespace();
+                Filter {
+                    name: split.next().unwrap(),
+                    args: split.collect(),
+                }
+            })
+            .collect();
+        let ctx = FilterContext {
+            path: "x",
+            timezone: &Timezone::Named(chrono_tz::Tz::UTC),
+            locale: None,
+        };
+        Filters::default().apply(value, &filters, &ctx)
+    }
+
+    #[test]
+    fn escaping() {
+        let value = json!("<b>Tom & \"Jerry\"</b>");
+        let ctx = FilterContext {
+            path: "x",
+            timezone: &Timezone::Local,
+            locale: None,
+        };
+        assert_eq!(
+            Filters::default().apply(value.clone(), &[], &ctx).unwrap(),
+            "&lt;b&gt;Tom &amp; &quot;Jerry&quot;&lt;/b&gt;"
+        );
+        assert_eq!(
+            apply(value.clone(), "safe").unwrap(),
+            "<b>Tom & \"Jerry\"</b>"
+        );
+        assert_eq!(
+            apply(value.clone(), "raw").unwrap(),
+            "<b>Tom & \"Jerry\"</b>"
+        );
+        assert_eq!(
+            apply(value, "escape | escape").unwrap(),
+            "&lt;b&gt;Tom &amp; &quot;Jerry&quot;&lt;/b&gt;"
+        );
+        assert_eq!(apply(json!(["<"]), "safe").unwrap(), "[\"<\"]");
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            escape_attr("a b=\"c\"'"),
+            "a&#x20;b&#x3D;&#x22;c&#x22;&#x27;"
+        );
+        assert_eq!(escape_attr("x-1.2_ü"), "x-1.2_ü");
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            sanitize_url("/docs/a b.html?x=1&y=ä"),
+            "/docs/a%20b.html?x=1&y=%C3%A4"
+        );
+        assert_eq!(
+            sanitize_url("https://example.com/\"x\""),
+            "https://example.com/%22x%22"
+        );
+        assert_eq!(
+            sanitize_url("mailto:me@example.com"),
+            "mailto:me@example.com"
+        );
+        assert_eq!(sanitize_url(" JavaScript:alert(1)"), "#");
+        assert_eq!(sanitize_url("data:text/html,x"), "#");
+        assert_eq!(sanitize_url("./a:b"), "./a:b");
+        assert_eq!(apply(json!("/?a=1&b=2"), "url").unwrap(), "/?a=1&amp;b=2");
+        assert_eq!(
+            apply(json!("a b&c/ä"), "urlencode").unwrap(),
+            "a%20b%26c%2F%C3%A4"
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(apply(json!(" Hey "), "trim | upper").unwrap(), "HEY");
+        assert_eq!(apply(json!("HeY"), "lower").unwrap(), "hey");
+        assert_eq!(apply(json!("a-b-c"), "replace - +").unwrap(), "a+b+c");
+        assert_eq!(
+            apply(json!("Hello, World! Ünïcode"), "slugify").unwrap(),
+            "hello-world-ünïcode"
+        );
+        assert_eq!(apply(json!("Hello World"), "truncate 6").unwrap(), "Hello…");
+        assert_eq!(
+            apply(json!("Hello World"), "truncate 5 ...").unwrap(),
+            "Hello..."
+        );
+        assert_eq!(apply(json!("Hello"), "truncate 5").unwrap(), "Hello");
+        assert!(matches!(
+            apply(json!("Hello"), "truncate x"),
+            Err(Error::InvalidFilterArgument("truncate", a)) if a == "x"
+        ));
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(apply(json!(null), "default none").unwrap(), "none");
+        assert_eq!(apply(json!(""), "default none").unwrap(), "none");
+        assert_eq!(apply(json!("x"), "default none").unwrap(), "x");
+        assert_eq!(apply(json!(false), "default none").unwrap(), "false");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(apply(json!("2024-05-01"), "date").unwrap(), "2024-05-01");
+        assert_eq!(apply(json!(1714557600), "date %H:%M").unwrap(), "10:00");
+        assert!(matches!(
+            apply(json!("soon"), "date"),
+            Err(Error::InvalidDate(p, _)) if p == "x"
+        ));
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            apply(json!({"a": "</script>"}), "json | safe").unwrap(),
+            r#"{"a":"\u003c/script\u003e"}"#
+        );
+        assert_eq!(apply(json!("x"), "json").unwrap(), "&quot;x&quot;");
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            apply(json!("*Hey* <b>you</b>"), "markdown").unwrap(),
+            "<em>Hey</em> &lt;b&gt;you&lt;/b&gt;"
+        );
+        assert_eq!(
+            apply(json!("a\n\nb"), "markdown").unwrap(),
+            "<p>a</p>\n<p>b</p>"
+        );
+        assert_eq!(
+            apply(
+                json!("[x](javascript:alert(1)) ![y](JavaScript:z)"),
+                "markdown"
+            )
+            .unwrap(),
+            r##"<a href="#">x</a> <img src="#" alt="y" />"##
+        );
+        assert_eq!(
+            apply(
+                json!("[x](https://a.org/?q=1) <mailto:a@b.org>"),
+                "markdown"
+            )
+            .unwrap(),
+            r#"<a href="https://a.org/?q=1">x</a> <a href="mailto:a@b.org">mailto:a@b.org</a>"#
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(
+            apply(json!("x"), "nope"),
+            Err(Error::UnknownFilter(f)) if f == "nope"
+        ));
+        assert!(matches!(
+            apply(json!("x"), "safe x"),
+            Err(Error::UnexpectedArgument(a)) if a == "x"
+        ));
+        assert!(matches!(
+            apply(json!("x"), "replace a"),
+            Err(Error::MissingArgument(_))
+        ));
+    }
+
+    #[test]
+    fn registry() {
+        let mut filters = Filters::default();
+        filters.register("shout", |f, _, _| Ok(map_text(f, |text| text + "!")));
+        let ctx = FilterContext {
+            path: "x",
+            timezone: &Timezone::Local,
+            locale: None,
+        };
+        let shout = [Filter {
+            name: "shout",
+            args: vec![],
+        }];
+        assert_eq!(filters.apply(json!("hey"), &shout, &ctx).unwrap(), "hey!");
+    }
+}
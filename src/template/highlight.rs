@@ -0,0 +1,272 @@
+use super::html::{attr_value, find_ignore_case, unescape_html};
+use super::site::{HighlightConfig, HighlightStyle};
+use std::fmt::Write;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    append_highlighted_html_for_styled_line, css_for_theme_with_class_style, ClassStyle,
+    ClassedHTMLGenerator, IncludeBackground,
+};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Name of the stylesheet for highlighting with CSS classes, which is written
+/// into the public directory of the output.
+pub const STYLESHEET_FILE: &str = "highlight.css";
+
+/// Prefix of the CSS classes of highlighted code, so that they do not clash
+/// with the site's own classes.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Highlights code blocks in rendered pages.
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+    style: HighlightStyle,
+}
+
+impl Highlighter {
+    pub fn new(config: &HighlightConfig) -> Self {
+        let mut themes = ThemeSet::load_defaults();
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            // The theme name is validated when loading the site config.
+            theme: themes.themes.remove(&config.theme).unwrap_or_default(),
+            style: config.style,
+        }
+    }
+
+    /// Returns the stylesheet for code highlighted with CSS classes, or
+    /// `None` if the code is highlighted with inline styles.
+    pub fn stylesheet(&self) -> Option<String> {
+        match self.style {
+            HighlightStyle::Inline => None,
+            HighlightStyle::Classes => {
+                css_for_theme_with_class_style(&self.theme, CLASS_STYLE).ok()
+            }
+        }
+    }
+
+    /// Highlights the code blocks in `html` which are written as
+    /// `<pre><code class="language-x">`, like those of fenced code blocks
+    /// in Markdown. Code blocks in unknown languages are kept as they are.
+    pub fn highlight_html(&self, html: &str) -> String {
+        let mut res = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(block) = find_code_block(rest) {
+            res += &rest[..block.start];
+            match self.syntax(block.language) {
+                Some(syntax) => match self.highlight(&unescape_html(block.code), syntax) {
+                    Some(code) => {
+                        res += "<pre";
+                        res += &self.pre_attrs(block.pre_attrs);
+                        res += ">";
+                        res += block.code_tag;
+                        res += &code;
+                        res += "</code></pre>";
+                    }
+                    None => res += &rest[block.start..block.end],
+                },
+                None => res += &rest[block.start..block.end],
+            }
+            rest = &rest[block.end..];
+        }
+        res += rest;
+        res
+    }
+
+    fn syntax(&self, language: &str) -> Option<&SyntaxReference> {
+        self.syntaxes.find_syntax_by_token(language)
+    }
+
+    /// Returns the attributes of the `<pre>` tag of a highlighted code block,
+    /// with the colors of the theme or the class styled by the stylesheet added.
+    fn pre_attrs(&self, attrs: &str) -> String {
+        let mut res = attrs.to_string();
+        match self.style {
+            HighlightStyle::Inline if attr_value(attrs, "style").is_none() => {
+                res += r#" style=""#;
+                let settings = &self.theme.settings;
+                if let Some(c) = settings.background {
+                    write!(res, "background-color:{};", hex(c)).expect("write to string");
+                }
+                if let Some(c) = settings.foreground {
+                    write!(res, "color:{};", hex(c)).expect("write to string");
+                }
+                res += "\"";
+            }
+            HighlightStyle::Classes if attr_value(attrs, "class").is_none() => {
+                res += r#" class="hl-code""#;
+            }
+            _ => {}
+        }
+        res
+    }
+
+    /// Returns the given code highlighted as HTML, or `None` if highlighting
+    /// fails.
+    fn highlight(&self, code: &str, syntax: &SyntaxReference) -> Option<String> {
+        let res = match self.style {
+            HighlightStyle::Inline => {
+                let mut lines = syntect::easy::HighlightLines::new(syntax, &self.theme);
+                let mut res = String::with_capacity(code.len() * 4);
+                for line in LinesWithEndings::from(code) {
+                    let regions = lines.highlight_line(line, &self.syntaxes);
+                    regions
+                        .and_then(|regions| {
+                            append_highlighted_html_for_styled_line(
+                                &regions,
+                                IncludeBackground::No,
+                                &mut res,
+                            )
+                        })
+                        .map_err(|err| log::warn!("failed highlighting code: {err}"))
+                        .ok()?;
+                }
+                res
+            }
+            HighlightStyle::Classes => {
+                let mut generator =
+                    ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+                for line in LinesWithEndings::from(code) {
+                    generator
+                        .parse_html_for_line_which_includes_newline(line)
+                        .map_err(|err| log::warn!("failed highlighting code: {err}"))
+                        .ok()?;
+                }
+                generator.finalize()
+            }
+        };
+        Some(res)
+    }
+}
+
+fn hex(c: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+}
+
+/// A code block `<pre><code class="language-x">...</code></pre>` in HTML.
+struct CodeBlock<'a> {
+    start: usize,
+    end: usize,
+    /// Attributes of the `<pre>` tag.
+    pre_attrs: &'a str,
+    /// The whole opening `<code>` tag.
+    code_tag: &'a str,
+    language: &'a str,
+    /// The escaped code.
+    code: &'a str,
+}
+
+/// Returns the first code block in `html` with a language.
+fn find_code_block(html: &str) -> Option<CodeBlock<'_>> {
+    let mut offset = 0;
+    loop {
+        let start = offset + find_ignore_case(&html[offset..], "<pre")?;
+        offset = start + 4;
+        if !html[offset..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace()) {
+            continue;
+        }
+        let pre_end = offset + html[offset..].find('>')?;
+        let pre_attrs = &html[offset..pre_end];
+
+        let code_start = pre_end + 1;
+        let after_pre = &html[code_start..];
+        let is_code = after_pre
+            .get(..5)
+            .is_some_and(|t| t.eq_ignore_ascii_case("<code"))
+            && after_pre[5..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace());
+        if !is_code {
+            continue;
+        }
+        let code_tag_end = code_start + after_pre.find('>')? + 1;
+        let code_tag = &html[code_start..code_tag_end];
+
+        let Some(language) =
+            attr_value(&code_tag[5..code_tag.len() - 1], "class").and_then(|classes| {
+                classes
+                    .split_ascii_whitespace()
+                    .find_map(|c| c.strip_prefix("language-"))
+            })
+        else {
+            continue;
+        };
+
+        let code_end = code_tag_end + find_ignore_case(&html[code_tag_end..], "</code>")?;
+        let close = &html[code_end + "</code>".len()..];
+        let close_len = close.len() - close.trim_start().len() + "</pre>".len();
+        if !close
+            .trim_start()
+            .get(..6)
+            .is_some_and(|t| t.eq_ignore_ascii_case("</pre>"))
+        {
+            continue;
+        }
+
+        return Some(CodeBlock {
+            start,
+            end: code_end + "</code>".len() + close_len,
+            pre_attrs,
+            code_tag,
+            language,
+            code: &html[code_tag_end..code_end],
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_highlight {
+    use super::*;
+
+    fn highlighter(style: HighlightStyle) -> Highlighter {
+        Highlighter::new(&HighlightConfig {
+            style,
+            ..HighlightConfig::default()
+        })
+    }
+
+    #[test]
+    fn code_blocks() {
+        let html =
+            r#"<p>x</p><pre class="a"><code class="x language-rust">let a = "&lt;";</code></pre>!"#;
+        let block = find_code_block(html).unwrap();
+        assert_eq!(block.pre_attrs, r#" class="a""#);
+        assert_eq!(block.code_tag, r#"<code class="x language-rust">"#);
+        assert_eq!(block.language, "rust");
+        assert_eq!(block.code, r#"let a = "&lt;";"#);
+        assert_eq!(&html[block.start..block.end], &html[8..html.len() - 1]);
+
+        assert!(find_code_block("<pre><code>x</code></pre>").is_none());
+        assert!(find_code_block("<prefix><code class=\"language-rust\">x</code></pre>").is_none());
+    }
+
+    #[test]
+    fn inline_styles() {
+        let html = "<pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n";
+        let res = highlighter(HighlightStyle::Inline).highlight_html(html);
+        assert!(res.starts_with(r#"<pre style="background-color:#"#));
+        assert!(res.contains(r#"<code class="language-rust"><span style=""#));
+        assert!(res.contains(">main</span>"));
+        assert!(res.ends_with("</code></pre>\n"));
+    }
+
+    #[test]
+    fn classes() {
+        let highlighter = highlighter(HighlightStyle::Classes);
+        let html = "<pre><code class=\"language-html\">&lt;b&gt;</code></pre>";
+        let res = highlighter.highlight_html(html);
+        assert!(
+            res.starts_with(r#"<pre class="hl-code"><code class="language-html"><span class="hl-"#)
+        );
+        assert!(res.contains("&lt;</span>"));
+        assert!(highlighter.stylesheet().unwrap().contains(".hl-code {"));
+    }
+
+    #[test]
+    fn unknown_languages() {
+        let html = "<pre><code class=\"language-nope\">x</code></pre>";
+        assert_eq!(
+            highlighter(HighlightStyle::Inline).highlight_html(html),
+            html
+        );
+    }
+}
@@ -0,0 +1,59 @@
+/// Returns the position of the first occurrence of `needle` in `haystack`,
+/// ignoring ASCII case.
+pub fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Returns the value of the attribute `name` in the given attributes of a tag.
+pub fn attr_value<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    while let Some(i) = find_ignore_case(rest, name) {
+        let preceded_by_space = rest[..i].ends_with(|c: char| c.is_ascii_whitespace());
+        let value = rest[i + name.len()..].trim_start();
+        rest = &rest[i + name.len()..];
+        let Some(value) = value.strip_prefix('=').filter(|_| preceded_by_space) else {
+            continue;
+        };
+        let value = value.trim_start();
+        return match value.chars().next() {
+            Some(q @ ('"' | '\'')) => value[1..].split(q).next(),
+            _ => value.split(|c: char| c.is_ascii_whitespace()).next(),
+        };
+    }
+    None
+}
+
+/// Decodes the character references created by HTML escaping.
+pub fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test_html {
+    use super::*;
+
+    #[test]
+    fn attributes() {
+        assert_eq!(attr_value(r#" class="a" id="b""#, "id"), Some("b"));
+        assert_eq!(attr_value(" id='b c'", "id"), Some("b c"));
+        assert_eq!(attr_value(" id = b", "id"), Some("b"));
+        assert_eq!(attr_value(r#" data-id="x""#, "id"), None);
+        assert_eq!(attr_value(r#" grid="x""#, "id"), None);
+    }
+
+    #[test]
+    fn unescaping() {
+        assert_eq!(
+            unescape_html("&lt;a href=&quot;x&quot;&gt;&amp;lt;&#39;"),
+            "<a href=\"x\">&lt;'"
+        );
+    }
+}
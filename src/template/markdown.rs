@@ -0,0 +1,111 @@
+use pulldown_cmark::{html, Options, Parser};
+
+/// Start and end of the placeholders for outputs of templates on Markdown
+/// pages. Characters of the Unicode private use area are not changed by
+/// rendering Markdown, except in link targets, where they are percent encoded.
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+const ENCODED_PLACEHOLDER_START: &str = "%EE%80%80";
+const ENCODED_PLACEHOLDER_END: &str = "%EE%80%81";
+
+const OPTIONS: Options = Options::ENABLE_TABLES
+    .union(Options::ENABLE_FOOTNOTES)
+    .union(Options::ENABLE_STRIKETHROUGH)
+    .union(Options::ENABLE_TASKLISTS)
+    .union(Options::ENABLE_HEADING_ATTRIBUTES);
+
+/// Returns whether the page file at `path` is written in Markdown.
+pub fn is_markdown(path: &std::path::Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"))
+}
+
+/// Returns the placeholder for the template output at `index`, which is
+/// inserted into Markdown instead of the output, so that outputs are not
+/// interpreted as Markdown.
+pub fn placeholder(index: usize) -> String {
+    format!("{PLACEHOLDER_START}{index}{PLACEHOLDER_END}")
+}
+
+/// Renders the Markdown `content` into HTML and replaces the placeholders in
+/// it with the given template `outputs`.
+pub fn to_html(content: &str, outputs: &[String]) -> String {
+    let mut res = String::with_capacity(content.len() * 3 / 2);
+    html::push_html(&mut res, Parser::new_ext(content, OPTIONS));
+    insert_outputs(&res, outputs)
+}
+
+/// Replaces the placeholders in `content` with the given template `outputs`.
+/// A placeholder which is the only content of a paragraph replaces the whole
+/// paragraph if its output is HTML, so that e.g. a shortcode on its own line
+/// is not wrapped in `<p>`.
+pub fn insert_outputs(content: &str, outputs: &[String]) -> String {
+    let content = content
+        .replace(ENCODED_PLACEHOLDER_START, &PLACEHOLDER_START.to_string())
+        .replace(ENCODED_PLACEHOLDER_END, &PLACEHOLDER_END.to_string());
+
+    let mut res = String::with_capacity(content.len());
+    let mut rest = content.as_str();
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let index_start = start + PLACEHOLDER_START.len_utf8();
+        let output = rest[index_start..]
+            .split_once(PLACEHOLDER_END)
+            .and_then(|(index, _)| Some((index.len(), outputs.get(index.parse::<usize>().ok()?)?)));
+        let Some((index_len, output)) = output else {
+            res += &rest[..index_start];
+            rest = &rest[index_start..];
+            continue;
+        };
+
+        let mut before = &rest[..start];
+        let mut after = &rest[index_start + index_len + PLACEHOLDER_END.len_utf8()..];
+        if output.trim_start().starts_with('<') {
+            if let (Some(b), Some(a)) = (before.strip_suffix("<p>"), after.strip_prefix("</p>")) {
+                before = b;
+                after = a;
+            }
+        }
+        res += before;
+        res += output;
+        rest = after;
+    }
+    res += rest;
+    res
+}
+
+#[cfg(test)]
+mod test_markdown {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let outputs = vec!["Tom &amp; Jerry".to_string(), "/a b".to_string()];
+        let content = format!(
+            "# {}\n\n*{}* [link]({})\n\n`{}`",
+            placeholder(0),
+            placeholder(0),
+            placeholder(1),
+            placeholder(0),
+        );
+        assert_eq!(
+            to_html(&content, &outputs),
+            "<h1>Tom &amp; Jerry</h1>\n<p><em>Tom &amp; Jerry</em> <a href=\"/a b\">link</a></p>\n<p><code>Tom &amp; Jerry</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let outputs = vec!["<div>\n\n*x*\n</div>".to_string(), "text".to_string()];
+        let content = format!("{}\n\n{}", placeholder(0), placeholder(1));
+        assert_eq!(
+            to_html(&content, &outputs),
+            "<div>\n\n*x*\n</div>\n<p>text</p>\n"
+        );
+    }
+
+    #[test]
+    fn invalid_placeholders() {
+        let content = format!("{PLACEHOLDER_START}x{PLACEHOLDER_END} {}", placeholder(5));
+        assert_eq!(insert_outputs(&content, &[]), content);
+    }
+}
@@ -1,143 +1,1651 @@
+pub mod cache;
+pub mod data;
+pub mod date;
+pub mod deps;
 pub mod errors;
+pub mod exec;
+pub mod filters;
+pub mod highlight;
+pub mod html;
+pub mod markdown;
 pub mod page;
 pub mod parsing;
+pub mod site;
+pub mod toc;
+pub mod value;
 
-use chrono::Local;
+use cache::{BuildCache, CachedPage};
+use chrono::{DateTime, FixedOffset, Locale, Utc};
+use date::Timezone;
+use deps::{classify_change, Change, Dependencies, RenderedPage};
 use errors::{Error, Result};
+use exec::{config_env_name, Exec, ExecMemo, ExecPolicy};
+use filters::{FilterContext, Filters};
+use highlight::Highlighter;
 use page::Page;
-use parsing::{find_next_template, find_template, Template};
+use parsing::{find_block_end, find_template, trim_pieces, ParsedTemplate, Piece, Template};
+use site::{LastModifiedSource, SiteConfig, SITE_CONFIG_FILE};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Name of the file marking a directory as output directory created by temple.
+pub const OUTPUT_MARKER_FILE: &str = ".temple-output";
 
 pub struct Builder {
-    public_dir: PathBuf,
+    source_dir: PathBuf,
+    /// Public directories ordered by priority, site first, then themes.
+    public_dirs: Vec<PathBuf>,
+    /// Directory of the public files relative to the output directory.
+    public_output: PathBuf,
     pages_dir: PathBuf,
-    templates_dir: PathBuf,
+    data_dir: PathBuf,
+    /// Template directories ordered by priority, site first, then themes.
+    templates_dirs: Vec<PathBuf>,
+    theme_dirs: Vec<PathBuf>,
 
     target_dir: PathBuf,
+    cache_dir: Option<PathBuf>,
+    force: bool,
+
+    max_depth: usize,
+    jobs: usize,
+    indent_templates: bool,
+
+    /// Fixed date of the build. The current date is used when not set.
+    date: Option<DateTime<Utc>>,
+    timezone: Timezone,
+    locale: Option<Locale>,
+    lastmodified: LastModifiedSource,
+
+    exec_policy: ExecPolicy,
+    /// Working directory of 'exec' commands.
+    exec_dir: PathBuf,
+    exec_timeout: Option<Duration>,
+    exec_env: BTreeMap<String, String>,
+    /// Outputs of 'exec' commands of the current build.
+    exec_memo: ExecMemo,
+    filters: Filters,
+    highlighter: Option<Highlighter>,
+
+    /// Already loaded and parsed templates by template name. The cache is
+    /// cleared by every full build and kept between incremental rebuilds,
+    /// which invalidate changed templates in [`Builder::rebuild`].
+    template_cache: Mutex<HashMap<String, Arc<ParsedTemplate>>>,
+
+    /// State of the last successful build used for incremental rebuilds.
+    state: Mutex<Option<BuildState>>,
+}
+
+/// Pages and their outputs and dependencies of a build.
+struct BuildState {
+    pages: Vec<Page>,
+    /// Rendered pages by their id.
+    rendered: HashMap<PathBuf, RenderedPage>,
+}
+
+/// State of rendering a single page.
+struct Context<'a> {
+    page: &'a Page,
+    pages: &'a [Page],
+    cache: Option<&'a BuildCache>,
+    /// Date of the build in the configured timezone.
+    date: DateTime<FixedOffset>,
+    /// Contents of the data files.
+    data: &'a serde_json::Value,
+    /// Variables accessible in templates by their name, which are the page
+    /// and the names bound by loops.
+    vars: serde_json::Map<String, serde_json::Value>,
+    /// Names of the templates currently being applied, used to detect cycles.
+    chain: Vec<String>,
+    /// Dependencies collected while rendering the page.
+    deps: Dependencies,
+    /// Outputs of the templates on a Markdown page, which are inserted after
+    /// rendering the Markdown. `None` while rendering HTML.
+    markdown: Option<Vec<String>>,
+    /// Bodies of the shortcodes currently being applied.
+    bodies: Vec<String>,
+    /// Headings of the page, collected once its content is rendered.
+    headings: Option<Vec<toc::Heading>>,
+}
+
+impl<'a> Context<'a> {
+    fn new(
+        page: &'a Page,
+        pages: &'a [Page],
+        cache: Option<&'a BuildCache>,
+        date: DateTime<FixedOffset>,
+        data: &'a serde_json::Value,
+    ) -> Self {
+        let mut vars = serde_json::Map::new();
+        vars.insert("page".into(), page.vars());
+        if let Some(record) = &page.record {
+            vars.insert("record".into(), record.clone());
+        }
+        Self {
+            page,
+            pages,
+            cache,
+            date,
+            data,
+            vars,
+            chain: vec![],
+            deps: Dependencies::default(),
+            markdown: markdown::is_markdown(&page.source).then(Vec::new),
+            bodies: vec![],
+            headings: None,
+        }
+    }
+
+    /// Returns the value of the variable at the dotted `path`, e.g.
+    /// `page.title` or `data.team.members`.
+    fn lookup(&self, path: &str) -> Result<&serde_json::Value> {
+        let (name, rest) = match path.split_once('.') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path, None),
+        };
+        let root = match self.vars.get(name) {
+            Some(v) => Some(v),
+            None if name == "data" => Some(self.data),
+            None => None,
+        };
+        match (root, rest) {
+            (Some(root), Some(rest)) => value::lookup(root, rest),
+            (root, _) => root,
+        }
+        .ok_or_else(|| Error::UndefinedVariable(path.to_string()))
+    }
 }
 
 impl Builder {
-    pub fn new(source_dir: impl Into<PathBuf>, target_dir: impl Into<PathBuf>) -> Builder {
+    pub fn new(
+        source_dir: impl Into<PathBuf>,
+        target_dir: impl Into<PathBuf>,
+        config: SiteConfig,
+    ) -> Builder {
         let source_dir = source_dir.into();
+        let theme_dirs: Vec<_> = config.themes.iter().map(|t| source_dir.join(t)).collect();
+        let dirs = || [&source_dir].into_iter().chain(&theme_dirs);
+        let exec_dir = match &config.exec.workdir {
+            Some(workdir) => source_dir.join(workdir),
+            None => source_dir.clone(),
+        };
         Builder {
-            public_dir: source_dir.join("public"),
-            pages_dir: source_dir.join("pages"),
-            templates_dir: source_dir.join("templates"),
+            public_dirs: dirs().map(|d| d.join(&config.public_dir)).collect(),
+            public_output: config.public_output,
+            pages_dir: source_dir.join(&config.pages_dir),
+            data_dir: source_dir.join(&config.data_dir),
+            templates_dirs: dirs().map(|d| d.join(&config.templates_dir)).collect(),
+            theme_dirs: theme_dirs.clone(),
+            source_dir: source_dir.clone(),
             target_dir: target_dir.into(),
+            cache_dir: config.cache_dir,
+            force: config.force,
+            max_depth: config.max_depth,
+            indent_templates: config.indent_templates,
+            jobs: config
+                .jobs
+                .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+                .unwrap_or(1),
+            date: config.date,
+            timezone: config.timezone,
+            locale: config.locale,
+            lastmodified: config.lastmodified,
+            exec_policy: ExecPolicy {
+                enabled: config.exec.enabled,
+                allow: config.exec.allow,
+            },
+            exec_dir,
+            exec_timeout: config.exec.timeout,
+            exec_env: config.exec.env,
+            exec_memo: ExecMemo::default(),
+            filters: Filters::default(),
+            highlighter: config.highlight.as_ref().map(Highlighter::new),
+            template_cache: Mutex::default(),
+            state: Mutex::default(),
         }
     }
 
     pub fn build(&self) -> Result<()> {
-        if self.target_dir.exists() {
-            fs::remove_dir_all(&self.target_dir)?;
+        self.state.lock().expect("build state lock").take();
+        self.exec_memo.clear();
+        // Rebuilds fall back to a full build without invalidating the changed
+        // templates, which may also have changed after a failed build.
+        self.template_cache
+            .lock()
+            .expect("template cache lock")
+            .clear();
+
+        if let Some(theme_dir) = self.theme_dirs.iter().find(|d| !d.is_dir()) {
+            return Err(Error::ThemeNotFound(theme_dir.clone()));
+        }
+
+        if !self.force {
+            self.check_target_dir()?;
+        }
+
+        let cache = self.cache_dir.as_ref().map(BuildCache::load);
+        let inputs = cache.as_ref().map(|_| self.inputs_hash()).transpose()?;
+
+        if let Some((cache, inputs)) = cache.as_ref().zip(inputs.as_ref()) {
+            if cache.is_up_to_date(inputs, &self.target_dir) {
+                log::info!("Nothing has changed since the last build");
+                self.restore_state(cache)?;
+                return Ok(());
+            }
+        }
+
+        // The site is built into a staging directory first, which replaces the
+        // output directory only after the build has succeeded. By that, the
+        // previous output stays intact if the build fails.
+        let staging_dir = sibling_dir(&self.target_dir, "tmp")?;
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        fs::create_dir_all(&staging_dir)?;
+
+        let build = || {
+            // Existing files are not overwritten when copying, so the site's
+            // public files are copied first to take precedence over the themes'
+            // ones.
+            for (i, public_dir) in self.public_dirs.iter().enumerate() {
+                if i > 0 && !public_dir.exists() {
+                    continue;
+                }
+                dircpy::copy_dir(public_dir, staging_dir.join(&self.public_output))?;
+            }
+            self.write_highlight_stylesheet(&staging_dir.join(&self.public_output))?;
+
+            File::create(staging_dir.join(OUTPUT_MARKER_FILE))?;
+
+            let pages = self.read_pages()?;
+            let rendered = self.render_pages(&pages, |_| true, cache.as_ref(), &staging_dir)?;
+            Ok((pages, rendered))
+        };
+
+        let (pages, rendered) = match build() {
+            Ok(res) => res,
+            Err(err) => {
+                if let Err(err) = fs::remove_dir_all(&staging_dir) {
+                    log::warn!("failed removing staging directory {staging_dir:?}: {err}");
+                }
+                return Err(err);
+            }
+        };
+
+        self.replace_target_dir(&staging_dir)?;
+
+        if let Some((cache, inputs)) = cache.zip(inputs) {
+            cache.save(inputs, &self.target_dir)?;
+        }
+
+        *self.state.lock().expect("build state lock") = Some(BuildState {
+            pages,
+            rendered: rendered.into_iter().collect(),
+        });
+
+        Ok(())
+    }
+
+    /// Ensures that replacing the output directory does not delete anything
+    /// which has not been created by temple. Fails if the output directory
+    /// equals, contains or is inside the source or a theme directory, contains
+    /// the current working directory or the home directory, or if it is an
+    /// existing non-empty directory which does not contain the output marker
+    /// file.
+    fn check_target_dir(&self) -> Result<()> {
+        let unsafe_dir = |reason: &str| {
+            Err(Error::UnsafeOutputDir(
+                self.target_dir.clone(),
+                reason.to_string(),
+            ))
+        };
+
+        let Some(target_dir) = canonicalize_lenient(&self.target_dir) else {
+            return Ok(());
+        };
+
+        let protected = [&self.source_dir]
+            .into_iter()
+            .chain(&self.theme_dirs)
+            .filter_map(|d| d.canonicalize().ok());
+        for dir in protected {
+            if dir.starts_with(&target_dir) {
+                return unsafe_dir(&format!("it contains the source directory {dir:?}"));
+            }
+            if target_dir.starts_with(&dir) {
+                return unsafe_dir(&format!("it is inside the source directory {dir:?}"));
+            }
+        }
+
+        if std::env::current_dir().is_ok_and(|d| d.starts_with(&target_dir)) {
+            return unsafe_dir("it contains the current working directory");
+        }
+
+        if dirs::home_dir()
+            .and_then(|d| d.canonicalize().ok())
+            .is_some_and(|d| d.starts_with(&target_dir))
+        {
+            return unsafe_dir("it contains the home directory");
+        }
+
+        if target_dir.is_file() {
+            return unsafe_dir("it is a file");
+        }
+
+        if target_dir.is_dir()
+            && !target_dir.join(OUTPUT_MARKER_FILE).exists()
+            && fs::read_dir(&target_dir)?.next().is_some()
+        {
+            return unsafe_dir("it is not empty and has not been created by temple");
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the output directory with the given `staging_dir`.
+    ///
+    /// The previous output is renamed aside first and only removed once the
+    /// staging directory took its place, so an interrupted build leaves either
+    /// the previous output or the renamed one next to the output directory.
+    /// If the staging directory can not be moved, the previous output is
+    /// moved back.
+    fn replace_target_dir(&self, staging_dir: &Path) -> Result<()> {
+        let old_dir = sibling_dir(&self.target_dir, "old")?;
+        if old_dir.exists() {
+            fs::remove_dir_all(&old_dir)?;
+        }
+
+        let has_output = self.target_dir.exists();
+        if has_output {
+            fs::rename(&self.target_dir, &old_dir)?;
+        }
+        if let Err(err) = fs::rename(staging_dir, &self.target_dir) {
+            if has_output {
+                if let Err(err) = fs::rename(&old_dir, &self.target_dir) {
+                    log::warn!("failed restoring previous output {old_dir:?}: {err}");
+                }
+            }
+            return Err(err.into());
+        }
+
+        // The new output is complete at this point, so failing to clean up
+        // does not fail the build.
+        if has_output {
+            if let Err(err) = fs::remove_dir_all(&old_dir) {
+                log::warn!("failed removing previous output {old_dir:?}: {err}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores the build state used for incremental rebuilds from the pages
+    /// of the previous build in the given `cache`.
+    fn restore_state(&self, cache: &BuildCache) -> Result<()> {
+        let pages = self.read_pages()?;
+        let rendered = pages
+            .iter()
+            .filter_map(|page| {
+                let cached = cache.page(&page.id)?;
+                let rendered = RenderedPage {
+                    output: self.output_path(page),
+                    deps: Dependencies {
+                        templates: cached.templates.iter().cloned().collect(),
+                        pages: cached.pages,
+                        files: cached.files.clone(),
+                        volatile: cached.volatile,
+                    },
+                };
+                Some((page.id.clone(), rendered))
+            })
+            .collect();
+
+        *self.state.lock().expect("build state lock") = Some(BuildState { pages, rendered });
+
+        Ok(())
+    }
+
+    /// Rebuilds only the outputs affected by the files at the given changed
+    /// `paths` since the last build. Unchanged outputs are not touched. Falls
+    /// back to a full build if there is no previous build or if the changes
+    /// can not be attributed to templates, pages, data or public files.
+    ///
+    /// Unlike a full build, affected outputs are written directly into the
+    /// output directory, so a failed rebuild can leave some of them updated
+    /// and others not.
+    pub fn rebuild(&self, paths: &[PathBuf]) -> Result<()> {
+        let Some(mut state) = self.state.lock().expect("build state lock").take() else {
+            return self.build();
+        };
+        self.exec_memo.clear();
+
+        let pages_dir = self.pages_dir.canonicalize()?;
+        let templates_dirs = canonicalize_all(&self.templates_dirs);
+        let public_dirs = canonicalize_all(&self.public_dirs);
+        let data_dir =
+            canonicalize_lenient(&self.data_dir).unwrap_or_else(|| self.data_dir.clone());
+
+        let mut changed_templates = HashSet::new();
+        let mut changed_bundles = HashSet::new();
+        let mut all_templates_changed = false;
+        let mut data_changed = false;
+        for path in paths.iter().filter_map(|p| canonicalize_lenient(p)) {
+            match classify_change(&path, &pages_dir, &templates_dirs, &public_dirs, &data_dir) {
+                Change::Template(name) => {
+                    self.template_cache
+                        .lock()
+                        .expect("template cache lock")
+                        .remove(&name);
+                    changed_templates.insert(name);
+                }
+                Change::Templates => {
+                    self.template_cache
+                        .lock()
+                        .expect("template cache lock")
+                        .clear();
+                    all_templates_changed = true;
+                }
+                Change::Public(rel) => {
+                    if !self.update_public_file(&rel)? {
+                        return self.build();
+                    }
+                }
+                Change::Page => {}
+                // Pages are not tracked for using data, so all of them are
+                // rendered again.
+                Change::Data => data_changed = true,
+                // Files removed from a bundle are only removed from the output
+                // by a full build.
+                Change::Bundle(_) if !path.exists() => return self.build(),
+                Change::Bundle(dir) => {
+                    changed_bundles.insert(self.pages_dir.join(dir));
+                }
+                Change::Other => return self.build(),
+            }
+        }
+
+        let pages = self.read_pages()?;
+        let pages_changed = nav_entries(&pages) != nav_entries(&state.pages);
+
+        // The other files of page bundles are only removed from the output by
+        // a full build, so it is needed when a bundle page is deleted or its
+        // output has moved.
+        let outputs: HashSet<_> = pages.iter().map(|p| self.output_path(p)).collect();
+        let bundle_removed = state
+            .pages
+            .iter()
+            .any(|p| p.bundle.is_some() && !outputs.contains(&self.output_path(p)));
+        if bundle_removed {
+            return self.build();
         }
 
-        dircpy::copy_dir(&self.public_dir, self.target_dir.join("public"))?;
+        let affected = |page: &Page| {
+            let Some(prev) = state.rendered.get(&page.id) else {
+                return true;
+            };
+            all_templates_changed
+                || data_changed
+                || (pages_changed && prev.deps.pages)
+                || !prev.deps.templates.is_disjoint(&changed_templates)
+                || page
+                    .bundle
+                    .as_ref()
+                    .is_some_and(|b| changed_bundles.contains(b))
+                || state.pages.iter().all(|p| p != page)
+        };
+
+        let rendered = self.render_pages(&pages, affected, None, &self.target_dir)?;
+        log::info!("Rebuilt {} of {} pages", rendered.len(), pages.len());
+
+        // Remove outputs of deleted pages or of pages whose output has moved.
+        for prev in state.rendered.values() {
+            if !outputs.contains(&prev.output) {
+                remove_output(&prev.output, &self.target_dir);
+            }
+        }
 
+        state
+            .rendered
+            .retain(|id, _| pages.iter().any(|p| &p.id == id));
+        state.rendered.extend(rendered);
+        state.pages = pages;
+
+        *self.state.lock().expect("build state lock") = Some(state);
+
+        Ok(())
+    }
+
+    fn output_path(&self, page: &Page) -> PathBuf {
+        page.config
+            .as_ref()
+            .and_then(|v| v.output.as_ref())
+            .map(|v| self.target_dir.join(v))
+            .unwrap_or_else(|| self.target_dir.join(&page.name).join("index.html"))
+    }
+
+    fn read_pages(&self) -> Result<Vec<Page>> {
         let mut entries =
             fs::read_dir(&self.pages_dir)?.collect::<std::result::Result<Vec<_>, _>>()?;
         entries.sort_by_key(|e| e.file_name());
 
-        let pages = entries
+        let mut pages = vec![];
+        for entry in entries {
+            let page = Page::read(entry.path())?;
+            match page
+                .config
+                .as_ref()
+                .and_then(|c| c.generate_from.as_deref())
+            {
+                Some(from) => pages.extend(page.generate(&self.read_records(from)?)?),
+                None => pages.push(page),
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Reads the list of records in the data file at `path` relative to the
+    /// source directory, which must be located in the data directory.
+    fn read_records(&self, path: &str) -> Result<Vec<serde_json::Value>> {
+        let invalid = || Error::InvalidGenerateFrom(path.to_string());
+
+        let rel = Path::new(path);
+        if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(invalid());
+        }
+        let file = self.source_dir.join(rel);
+        if !file.starts_with(&self.data_dir) {
+            return Err(invalid());
+        }
+
+        match data::read_data_file(&file)?.ok_or_else(invalid)? {
+            serde_json::Value::Array(records) => Ok(records),
+            _ => Err(Error::NotIterable(path.to_string())),
+        }
+    }
+
+    /// Renders all `pages` matching `filter` in parallel and writes them to
+    /// their output paths. Returns the rendered pages by their id.
+    ///
+    /// When a `cache` is passed, the output of pages whose source and
+    /// dependencies did not change since the previous build is taken from the
+    /// cache instead of rendering the page again.
+    ///
+    /// The pages are written into `out_dir`, which is either the output
+    /// directory or a staging directory replacing it afterwards.
+    fn render_pages(
+        &self,
+        pages: &[Page],
+        filter: impl Fn(&Page) -> bool,
+        cache: Option<&BuildCache>,
+        out_dir: &Path,
+    ) -> Result<Vec<(PathBuf, RenderedPage)>> {
+        let outpaths: Vec<_> = pages.iter().map(|p| self.output_path(p)).collect();
+        for (i, outpath) in outpaths.iter().enumerate() {
+            if let Some(j) = outpaths[..i].iter().position(|p| p == outpath) {
+                return Err(Error::DuplicateOutput(
+                    outpath.clone(),
+                    pages[j].name.clone(),
+                    pages[i].name.clone(),
+                ));
+            }
+        }
+
+        let jobs: Vec<_> = pages
             .iter()
-            .map(|entry| Page::read(entry.path()))
-            .collect::<std::result::Result<Vec<_>, _>>()?;
+            .zip(outpaths)
+            .filter(|(page, _)| filter(page))
+            .collect();
+
+        let data = data::load_data(&self.data_dir)?;
+        // Pages are not tracked for using data, so changed data invalidates
+        // all cached pages.
+        let base_key = cache.map(|_| cache::hash([self.base_key(), data.to_string()]));
+        // All pages of a build share the same date.
+        let date = self.timezone.convert(self.date.unwrap_or_else(Utc::now));
+
+        try_map_parallel(&jobs, self.jobs, |(page, outpath)| {
+            let writepath =
+                out_dir.join(outpath.strip_prefix(&self.target_dir).expect("output path"));
+            let write_dir = writepath.parent().expect("parent dir");
+            if !write_dir.exists() {
+                fs::create_dir_all(write_dir)?;
+            }
+            if let Some(bundle) = &page.bundle {
+                self.copy_bundle_files(page, bundle, write_dir, outpath)?;
+            }
+
+            let cached = cache
+                .zip(base_key.as_deref())
+                .and_then(|(cache, base_key)| {
+                    let cached = cache.page(&page.id)?;
+                    let key = self.page_key(base_key, page, pages, cached)?;
+                    let output = cache.page_output(&page.id, &key)?;
+                    Some((cache, cached.clone(), output))
+                });
+
+            if let Some((cache, cached, output)) = cached {
+                log::debug!("Using cached output of page '{}' ...", page.name);
+                write_output(&writepath, &output, outpath)?;
+                cache.store_page(&page.id, cached.clone(), &output)?;
+                let rendered = RenderedPage {
+                    output: outpath.clone(),
+                    deps: Dependencies {
+                        templates: cached.templates.into_iter().collect(),
+                        pages: cached.pages,
+                        files: cached.files,
+                        volatile: cached.volatile,
+                    },
+                };
+                return Ok((page.id.clone(), rendered));
+            }
 
-        for page in &pages {
             log::debug!("Processing page '{}' ...", page.name);
 
-            let parsedcontent = self.apply_template(&page.content, page, &pages)?;
+            let mut ctx = Context::new(page, pages, cache, date, &data);
+            let mut parsedcontent = self.apply_template(&page.content, &mut ctx)?;
+            if let Some(outputs) = ctx.markdown.take() {
+                parsedcontent = markdown::to_html(&parsedcontent, &outputs);
+            }
+            let headings = match ctx.headings.take() {
+                Some(headings) => headings,
+                None => {
+                    let (content, headings) = toc::anchor_headings(&parsedcontent);
+                    parsedcontent = content;
+                    headings
+                }
+            };
+            let mut parsedcontent = toc::insert_tocs(&parsedcontent, &headings);
+            if let Some(highlighter) = &self.highlighter {
+                parsedcontent = highlighter.highlight_html(&parsedcontent);
+            }
 
-            let outpath = page
-                .config
-                .as_ref()
-                .and_then(|v| v.output.as_ref())
-                .map(|v| self.target_dir.join(v))
-                .unwrap_or_else(|| self.target_dir.join(&page.name).join("index.html"));
+            write_output(&writepath, parsedcontent.as_bytes(), outpath)?;
 
-            let out_dir = outpath.parent().expect("parent dir");
-            if !out_dir.exists() {
-                fs::create_dir_all(out_dir)?;
+            if let Some((cache, base_key)) = cache.zip(base_key.as_deref()) {
+                let mut cached = CachedPage {
+                    key: String::new(),
+                    output: cache::hash([&parsedcontent]),
+                    templates: ctx.deps.templates.iter().cloned().collect(),
+                    pages: ctx.deps.pages,
+                    files: ctx.deps.files.clone(),
+                    volatile: ctx.deps.volatile,
+                };
+                if let Some(key) = self.page_key(base_key, page, pages, &cached) {
+                    cached.key = key;
+                    cache.store_page(&page.id, cached, parsedcontent.as_bytes())?;
+                }
             }
 
-            let mut f = File::create(outpath)?;
-            write!(f, "{parsedcontent}")?;
+            let rendered = RenderedPage {
+                output: outpath.clone(),
+                deps: ctx.deps,
+            };
+            Ok((page.id.clone(), rendered))
+        })
+    }
+
+    /// Returns the hash over all inputs which affect every page.
+    fn base_key(&self) -> String {
+        let config = fs::read(self.source_dir.join(SITE_CONFIG_FILE)).unwrap_or_default();
+        // 'exec' can also be disabled or restricted and the date and timezone
+        // can be set via command line or environment, which must not reuse
+        // outputs of pages built with other settings.
+        let settings = format!(
+            "{} {:?} {:?} {:?}",
+            self.exec_policy.enabled, self.exec_policy.allow, self.date, self.timezone
+        );
+        cache::hash([
+            env!("CARGO_PKG_VERSION").as_bytes(),
+            &config,
+            settings.as_bytes(),
+        ])
+    }
+
+    /// Returns the hash over the given page and the dependencies of the
+    /// `cached` page, or `None` if any of them can not be read.
+    fn page_key(
+        &self,
+        base_key: &str,
+        page: &Page,
+        pages: &[Page],
+        cached: &CachedPage,
+    ) -> Option<String> {
+        let mut parts = vec![
+            base_key.as_bytes().to_vec(),
+            fs::read(&page.source).ok()?,
+            self.output_path(page).to_string_lossy().as_bytes().to_vec(),
+            format!("{:?}", page.record).into_bytes(),
+        ];
+        for name in &cached.templates {
+            parts.push(name.as_bytes().to_vec());
+            parts.push(self.get_template(name).ok()?.text().as_bytes().to_vec());
+        }
+        // Files run or read by 'exec' are not covered by the cache keys of
+        // the commands, as these are only known after rendering the page.
+        for path in cached.files.keys() {
+            parts.push(path.as_bytes().to_vec());
+            parts.push(fs::read(path).ok()?);
         }
+        if cached.pages {
+            parts.push(format!("{:?}", nav_entries(pages)).into_bytes());
+        }
+        Some(cache::hash(parts))
+    }
 
+    /// Returns the hash over all files in the source, template and public
+    /// directories which are used in the build.
+    fn inputs_hash(&self) -> Result<String> {
+        let mut parts = vec![
+            self.base_key().into_bytes(),
+            self.target_dir.to_string_lossy().as_bytes().to_vec(),
+        ];
+        let dirs = [&self.pages_dir, &self.data_dir]
+            .into_iter()
+            .chain(&self.templates_dirs)
+            .chain(&self.public_dirs);
+        for dir in dirs {
+            parts.push(dir.to_string_lossy().as_bytes().to_vec());
+            for path in cache::walk_files(dir)? {
+                parts.push(path.to_string_lossy().as_bytes().to_vec());
+                parts.push(fs::read(&path)?);
+            }
+        }
+        Ok(cache::hash(parts))
+    }
+
+    /// Copies all files of the page `bundle` except the page itself into
+    /// `write_dir`, which is the directory the page is written to. `outpath` is
+    /// the output path of the page in the output directory.
+    fn copy_bundle_files(
+        &self,
+        page: &Page,
+        bundle: &Path,
+        write_dir: &Path,
+        outpath: &Path,
+    ) -> Result<()> {
+        let out_dir = outpath.parent().expect("parent dir");
+        for file in cache::walk_files(bundle)? {
+            if file == page.source {
+                continue;
+            }
+            let rel = file.strip_prefix(bundle).expect("bundle file");
+            let target = write_dir.join(rel);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_output(&target, &fs::read(&file)?, &out_dir.join(rel))?;
+        }
         Ok(())
     }
 
-    fn apply_template(&self, content: &str, page: &Page, pages: &[Page]) -> Result<String> {
-        let mut content = content.trim().to_string();
+    /// Writes the stylesheet for code highlighted with CSS classes into the
+    /// given public output directory, unless the site has its own.
+    fn write_highlight_stylesheet(&self, public_dir: &Path) -> Result<()> {
+        let Some(css) = self.highlighter.as_ref().and_then(Highlighter::stylesheet) else {
+            return Ok(());
+        };
+        let path = public_dir.join(highlight::STYLESHEET_FILE);
+        if !path.exists() {
+            fs::create_dir_all(public_dir)?;
+            fs::write(path, css)?;
+        }
+        Ok(())
+    }
 
-        while let Some(t) = find_next_template(&content)? {
-            content = match t.template {
+    /// Copies the public file at `rel` from the public directory with the
+    /// highest priority containing it into the output directory, or removes it
+    /// from the output directory if it does not exist anymore. Returns `false`
+    /// if the change can not be applied to a single file.
+    fn update_public_file(&self, rel: &Path) -> Result<bool> {
+        let target = self.target_dir.join(&self.public_output).join(rel);
+        match self
+            .public_dirs
+            .iter()
+            .map(|d| d.join(rel))
+            .find(|p| p.exists())
+        {
+            Some(source) if source.is_file() => {
+                let contents = fs::read(source)?;
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                write_if_changed(&target, &contents)?;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None if target.is_dir() => Ok(false),
+            None => {
+                remove_output(&target, &self.target_dir);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Applies all templates in the given content.
+    fn apply_template(&self, content: &str, ctx: &mut Context) -> Result<String> {
+        self.render(ParsedTemplate::parse(content)?.pieces(), ctx)
+    }
+
+    /// Applies all templates in a template file, which is HTML also when used
+    /// on a Markdown page.
+    fn apply_template_file(&self, template: &ParsedTemplate, ctx: &mut Context) -> Result<String> {
+        let markdown = ctx.markdown.take();
+        let res = self.render(template.pieces(), ctx);
+        ctx.markdown = markdown;
+        res
+    }
+
+    /// Applies all templates in the given pieces of content.
+    ///
+    /// On Markdown pages, the outputs of templates are replaced with
+    /// placeholders, which are replaced by the outputs again after rendering
+    /// the Markdown. Only the output of loops is Markdown itself.
+    fn render(&self, pieces: &[Piece], ctx: &mut Context) -> Result<String> {
+        // Outputs are not searched for templates again, so that e.g. values
+        // containing `{{` can not run templates.
+        let mut res = String::new();
+        let mut i = 0;
+        while let Some(piece) = pieces.get(i) {
+            i += 1;
+            let t = match piece {
+                Piece::Text(text) => {
+                    res += text;
+                    continue;
+                }
+                Piece::Template(t) => t,
+            };
+            let is_loop = matches!(t.template, Template::For { .. });
+            let output = match t.template {
                 Template::Extends { name } => {
-                    let mut content = t.remove_between(&content);
-                    let template_contents = self.get_template_content(name)?;
-                    let pagecontent_tpl = find_template(template_contents.trim(), "pagecontent")?
+                    let template = self.get_template(name)?;
+                    let pagecontent = find_template(template.pieces(), "pagecontent")
                         .ok_or(Error::ExtendWithNoPageContent)?;
-                    content = pagecontent_tpl.insert_between(&template_contents, &content);
-                    self.apply_template(&content, page, pages)?
+                    enter_template(&mut ctx.chain, name, self.max_depth)?;
+                    ctx.deps.templates.insert(name.to_string());
+
+                    // The rest of the page is rendered first and inserted into
+                    // the rendered template, so that it is not searched for
+                    // templates again. Markdown pages are rendered into HTML
+                    // before, as the template is HTML.
+                    res += &self.render(&pieces[i..], ctx)?;
+                    let mut page = res.trim().to_string();
+                    if let Some(outputs) = ctx.markdown.take() {
+                        page = markdown::to_html(&page, &outputs);
+                    }
+                    // Only headings of the page itself are anchored and listed
+                    // in tables of contents, not those of its templates.
+                    if ctx.headings.is_none() {
+                        let (anchored, headings) = toc::anchor_headings(&page);
+                        page = anchored;
+                        ctx.headings = Some(headings);
+                    }
+                    let Piece::Template(pagecontent_tpl) = &template.pieces()[pagecontent] else {
+                        unreachable!("pagecontent is a template");
+                    };
+                    let page =
+                        self.indent_included(page, template.text(), pagecontent_tpl.start_pos);
+                    let placeholder = markdown::placeholder(0);
+                    let mut template_pieces = template.pieces().to_vec();
+                    template_pieces[pagecontent] = Piece::Text(&placeholder);
+                    let content = self.render(&template_pieces, ctx)?;
+                    ctx.chain.pop();
+                    return Ok(content.replace(&placeholder, &page));
                 }
                 Template::Use { name } => {
-                    let template_contents =
-                        self.apply_template(&self.get_template_content(name)?, page, pages)?;
-                    t.insert_between(&content, &template_contents)
+                    let template = self.get_template(name)?;
+                    enter_template(&mut ctx.chain, name, self.max_depth)?;
+                    ctx.deps.templates.insert(name.to_string());
+                    let output = self.apply_template_file(&template, ctx)?;
+                    ctx.chain.pop();
+                    self.indent_included(output, &res, res.len())
+                }
+                Template::Call {
+                    name,
+                    ref args,
+                    block,
+                } => {
+                    // When the template opens a block closed by 'endcall', the
+                    // rendered content of the block is the shortcode's body.
+                    let body = match block {
+                        true => {
+                            let end = find_block_end(&pieces[i..], "call", "endcall")
+                                .ok_or(Error::UnclosedBlock("call"))?;
+                            let body = self.render(&pieces[i..i + end], ctx)?;
+                            i += end + 1;
+                            match &ctx.markdown {
+                                Some(outputs) => markdown::to_html(body.trim(), outputs),
+                                None => body,
+                            }
+                        }
+                        false => String::new(),
+                    };
+
+                    let name = format!("shortcodes/{name}");
+                    let template = self.get_template(&name)?;
+                    enter_template(&mut ctx.chain, &name, self.max_depth)?;
+                    ctx.deps.templates.insert(name.clone());
+
+                    let args: serde_json::Map<_, _> = args
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), serde_json::Value::from(*v)))
+                        .collect();
+                    let shadowed = ctx.vars.insert("args".into(), args.into());
+                    ctx.bodies.push(body);
+                    let output = self.apply_template_file(&template, ctx);
+                    ctx.bodies.pop();
+                    match shadowed {
+                        Some(shadowed) => ctx.vars.insert("args".into(), shadowed),
+                        None => ctx.vars.remove("args"),
+                    };
+                    ctx.chain.pop();
+                    self.indent_included(output?, &res, res.len())
                 }
-                Template::PageName => t.insert_between(&content, &page.name),
+                Template::EndCall => return Err(Error::UnmatchedBlockEnd("endcall")),
+                Template::Body => ctx
+                    .bodies
+                    .last()
+                    .ok_or(Error::BodyOutsideShortcode)?
+                    .clone(),
+                Template::PageName => filters::escape_html(&ctx.page.name),
                 Template::NavItems => {
-                    let mut navitems = Vec::with_capacity(pages.len());
-                    for p in pages {
+                    ctx.deps.pages = true;
+                    let mut navitems = Vec::with_capacity(ctx.pages.len());
+                    for p in ctx.pages {
                         if p.config.as_ref().is_some_and(|c| c.navignore) {
                             continue;
                         }
-                        let path = p
-                            .config
-                            .as_ref()
-                            .and_then(|c| c.path.clone())
-                            .unwrap_or_else(|| format!("/{}", p.name));
-                        let active = if p.name == page.name { r#" class="active""# } else { "" };
-                        let name = &p.name;
+                        let path = filters::escape_html(&filters::sanitize_url(&p.path()));
+                        let active =
+                            if p.name == ctx.page.name { r#" class="active""# } else { "" };
+                        let name = filters::escape_html(&p.name);
                         navitems.push(format!(r#"<a href="{path}"{active}>{name}</a>"#));
                     }
-                    t.insert_between(&content, &navitems.join("\n"))
+                    navitems.join("\n")
                 }
+                Template::Toc { min, max } => toc::marker(min, max),
                 Template::CurrentDate { ref format } => {
-                    let date = Local::now()
-                        .format(
-                            format
-                                .as_ref()
-                                .map(|v| v.as_str())
-                                .unwrap_or_else(|| "%Y-%m-%d %H:%M:%S"),
-                        )
-                        .to_string();
-                    t.insert_between(&content, &date)
-                }
-                Template::Exec { command, ref args } => {
-                    let res = Command::new(command).args(args).output()?;
-                    if !res.status.success() {
-                        return Err(Error::ExecCommandFailed(
-                            res.status,
-                            String::from_utf8_lossy(&res.stderr).to_string(),
+                    // A date given for the build is part of the cache key.
+                    if self.date.is_none() {
+                        ctx.deps.volatile = true;
+                    }
+                    let format = format.as_deref().unwrap_or("%Y-%m-%d %H:%M:%S");
+                    date::format_date(&ctx.date, format, self.locale)?
+                }
+                Template::Date { value, ref format } => {
+                    let v = ctx.lookup(value)?;
+                    let date = date::value_date(v, &self.timezone)
+                        .ok_or_else(|| Error::InvalidDate(value.to_string(), v.to_string()))?;
+                    let format = format.as_deref().unwrap_or("%Y-%m-%d");
+                    date::format_date(&date, format, self.locale)?
+                }
+                Template::LastModified { ref format } => {
+                    let source = &ctx.page.source;
+                    let date = match self.lastmodified {
+                        LastModifiedSource::Git => date::git_last_modified(source),
+                        LastModifiedSource::Mtime => None,
+                    };
+                    let date = match date {
+                        Some(date) => date,
+                        None => date::file_modified(source)?,
+                    };
+                    let format = format.as_deref().unwrap_or("%Y-%m-%d");
+                    date::format_date(&self.timezone.convert(date), format, self.locale)?
+                }
+                Template::Exec {
+                    ref options,
+                    command,
+                    ref args,
+                    ref filters,
+                    block,
+                } => {
+                    if !self.exec_policy.allows(command) {
+                        return Err(Error::ExecNotAllowed(
+                            ctx.page.name.clone(),
+                            command.to_string(),
                         ));
                     }
-                    t.insert_between(&content, &String::from_utf8_lossy(&res.stdout))
+
+                    // When the template opens a block closed by 'endexec', the
+                    // rendered content of the block is passed to the command.
+                    let input = match block {
+                        true => {
+                            let end = find_block_end(&pieces[i..], "exec", "endexec")
+                                .ok_or(Error::UnclosedBlock("exec"))?;
+                            let input = self.render(&trim_pieces(&pieces[i..i + end]), ctx)?;
+                            i += end + 1;
+                            match &ctx.markdown {
+                                Some(outputs) => Some(markdown::insert_outputs(&input, outputs)),
+                                None => Some(input),
+                            }
+                        }
+                        false => None,
+                    };
+                    let exec = Exec {
+                        command,
+                        args,
+                        cwd: &self.exec_dir,
+                        env: self
+                            .exec_env
+                            .iter()
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                            .collect(),
+                        page_env: self.page_env(ctx.page)?,
+                        perpage: options.perpage,
+                        timeout: options.timeout.or(self.exec_timeout),
+                        stdin: input.as_deref(),
+                    };
+                    for path in exec.files() {
+                        let hash = cache::hash([fs::read(&path)?]);
+                        ctx.deps
+                            .files
+                            .insert(path.to_string_lossy().to_string(), hash);
+                    }
+                    let output = if options.nocache {
+                        ctx.deps.volatile = true;
+                        exec.run()?
+                    } else {
+                        let key = exec.cache_key();
+                        self.exec_memo.get_or_run(&key, || {
+                            if let Some(output) = ctx.cache.and_then(|c| c.exec_output(&key)) {
+                                return Ok(output);
+                            }
+                            let output = exec.run()?;
+                            if let Some(cache) = ctx.cache {
+                                cache.store_exec_output(&key, &output);
+                            }
+                            Ok(output)
+                        })?
+                    };
+
+                    // Like values of variables, the output is escaped unless
+                    // marked as safe by a filter.
+                    let filter_ctx = FilterContext {
+                        path: command,
+                        timezone: &self.timezone,
+                        locale: self.locale,
+                    };
+                    self.filters.apply(output.into(), filters, &filter_ctx)?
+                }
+                Template::EndExec => return Err(Error::UnmatchedBlockEnd("endexec")),
+                Template::For { name, value } => {
+                    let end = find_block_end(&pieces[i..], "for", "endfor")
+                        .ok_or(Error::UnclosedBlock("for"))?;
+                    let body = &pieces[i..i + end];
+                    i += end + 1;
+
+                    let items = match ctx.lookup(value)? {
+                        serde_json::Value::Array(items) => items.clone(),
+                        _ => return Err(Error::NotIterable(value.to_string())),
+                    };
+
+                    let shadowed = ctx.vars.remove(name);
+                    let mut output = String::new();
+                    for item in items {
+                        ctx.vars.insert(name.to_string(), item);
+                        output += &self.render(body, ctx)?;
+                    }
+                    ctx.vars.remove(name);
+                    if let Some(shadowed) = shadowed {
+                        ctx.vars.insert(name.to_string(), shadowed);
+                    }
+                    output
+                }
+                Template::EndFor => return Err(Error::UnmatchedBlockEnd("endfor")),
+                Template::Var { path, ref filters } => {
+                    // Templates are parsed before the variables are known, so
+                    // single names are only variables if they are defined.
+                    if !path.contains('.') && !ctx.vars.contains_key(path) {
+                        return Err(Error::UnknownTemplate(path.to_string()));
+                    }
+                    // Undefined variables can be given a value by 'default'.
+                    let value = match ctx.lookup(path) {
+                        Ok(value) => value.clone(),
+                        Err(Error::UndefinedVariable(_))
+                            if filters.iter().any(|f| f.name == "default") =>
+                        {
+                            serde_json::Value::Null
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    let filter_ctx = FilterContext {
+                        path,
+                        timezone: &self.timezone,
+                        locale: self.locale,
+                    };
+                    self.filters.apply(value, filters, &filter_ctx)?
                 }
                 Template::PageContent => return Err(Error::ToplevelPageContent),
             };
+            let output = match &mut ctx.markdown {
+                Some(outputs) if !is_loop => {
+                    outputs.push(output);
+                    markdown::placeholder(outputs.len() - 1)
+                }
+                _ => output,
+            };
+
+            res += &output;
         }
 
-        Ok(content)
+        Ok(res)
     }
 
-    fn get_template_content(&self, name: &str) -> Result<String> {
+    /// Indents all lines of the `included` content but the first like the
+    /// line of the template at `pos` in `content`, if enabled and the template
+    /// is only preceded by whitespace on its line.
+    fn indent_included(&self, included: String, content: &str, pos: usize) -> String {
+        match line_indent(content, pos) {
+            Some(indent) if self.indent_templates => indent_lines(&included, indent),
+            _ => included,
+        }
+    }
+
+    /// Returns the environment variables describing the given page, which are
+    /// passed to 'exec' commands on it: the page's name, navigation path and
+    /// absolute output path as well as all values of its page config.
+    fn page_env(&self, page: &Page) -> Result<Vec<(String, String)>> {
+        let mut env = vec![];
+        let output = std::path::absolute(self.output_path(page))?;
+        env.push(("TEMPLE_PAGE_NAME".into(), page.name.clone()));
+        env.push(("TEMPLE_PAGE_PATH".into(), page.path()));
+        env.push(("TEMPLE_OUTPUT".into(), output.to_string_lossy().to_string()));
+
+        for (key, value) in &page.front_matter {
+            let value = match value {
+                toml::Value::String(v) => v.clone(),
+                v => v.to_string(),
+            };
+            env.push((config_env_name(key), value));
+        }
+
+        Ok(env)
+    }
+
+    /// Returns the parsed template `name`, loading it from the first
+    /// templates directory containing it if it is not cached yet, so site
+    /// templates override theme templates.
+    fn get_template(&self, name: &str) -> Result<Arc<ParsedTemplate>> {
+        if let Some(template) = self
+            .template_cache
+            .lock()
+            .expect("template cache lock")
+            .get(name)
+        {
+            return Ok(template.clone());
+        }
+
+        let template = Arc::new(ParsedTemplate::parse(&self.read_template_content(name)?)?);
+        self.template_cache
+            .lock()
+            .expect("template cache lock")
+            .insert(name.to_string(), template.clone());
+        Ok(template)
+    }
+
+    fn read_template_content(&self, name: &str) -> Result<String> {
+        let file_name = template_file_name(name)?;
+        let path = self
+            .templates_dirs
+            .iter()
+            .map(|d| d.join(&file_name))
+            .find(|p| p.is_file())
+            .ok_or_else(|| Error::TemplateNotFound(name.to_string()))?;
         let mut content = String::new();
-        File::open(self.templates_dir.join(format!("{name}.html")))?
-            .read_to_string(&mut content)?;
+        File::open(path)?.read_to_string(&mut content)?;
         Ok(content.to_string())
     }
 }
+
+/// Calls `f` for each item in `items` using up to `jobs` threads and returns
+/// the results in the order of `items`. Items are picked up in order and no
+/// new items are picked up after the first failure. Because all items before a
+/// failed one have been started, the error of the first failing item is
+/// returned, independent of the thread scheduling.
+fn try_map_parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Result<Vec<R>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    let mut results = thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = vec![];
+                    while !failed.load(Ordering::SeqCst) {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        let res = f(item);
+                        if res.is_err() {
+                            failed.store(true, Ordering::SeqCst);
+                        }
+                        results.push((i, res));
+                    }
+                    results
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("worker thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, res)| res).collect()
+}
+
+/// Writes `contents` to the file at `path`, unless the file already has the
+/// exact same contents, so that its modification time is preserved.
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<()> {
+    if fs::read(path).is_ok_and(|existing| existing == contents) {
+        return Ok(());
+    }
+    File::create(path)?.write_all(contents)?;
+    Ok(())
+}
+
+/// Writes `contents` to the file at `path`. If the file at `previous`, which
+/// is the same file in the current output directory, has the exact same
+/// contents, it is linked instead, so that its modification time is preserved.
+fn write_output(path: &Path, contents: &[u8], previous: &Path) -> Result<()> {
+    if path != previous
+        && fs::read(previous).is_ok_and(|existing| existing == contents)
+        && fs::hard_link(previous, path).is_ok()
+    {
+        return Ok(());
+    }
+    write_if_changed(path, contents)
+}
+
+/// Returns the path of a hidden directory next to `dir` with the given
+/// `suffix`, e.g. `.dist.tmp` for `dist`.
+fn sibling_dir(dir: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = dir
+        .file_name()
+        .ok_or_else(|| Error::InvalidOutputDir(dir.to_path_buf()))?;
+    Ok(dir.with_file_name(format!(".{}.{suffix}", name.to_string_lossy())))
+}
+
+/// Removes the output file at `path` and its parent directories within
+/// `target_dir` which become empty by that.
+fn remove_output(path: &Path, target_dir: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        log::warn!("failed removing output {path:?}: {err}");
+        return;
+    }
+    let mut dir = path.parent();
+    while let Some(d) = dir.filter(|d| d.starts_with(target_dir) && *d != target_dir) {
+        if fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// Canonicalizes all existing `paths` and drops the ones which do not exist.
+fn canonicalize_all(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths.iter().filter_map(|p| p.canonicalize().ok()).collect()
+}
+
+/// Returns the entries of the `navitems` listing of the given pages.
+fn nav_entries(pages: &[Page]) -> Vec<(&str, Option<&str>, bool)> {
+    pages
+        .iter()
+        .map(|p| {
+            let config = p.config.as_ref();
+            (
+                p.name.as_str(),
+                config.and_then(|c| c.path.as_deref()),
+                config.is_some_and(|c| c.navignore),
+            )
+        })
+        .collect()
+}
+
+/// Like [`Path::canonicalize`], but also works for paths which do not exist
+/// (anymore) as long as their parent directory exists.
+fn canonicalize_lenient(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok().or_else(|| {
+        let parent = path.parent()?.canonicalize().ok()?;
+        Some(parent.join(path.file_name()?))
+    })
+}
+
+/// Returns the file path of the template `name` relative to the templates
+/// directory. Names may contain subdirectories separated by `/` (e.g.
+/// `components/card`), but must not leave the templates directory.
+fn template_file_name(name: &str) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(Error::InvalidTemplateName(name.to_string()));
+    }
+    Ok(PathBuf::from(format!("{name}.html")))
+}
+
+/// Returns the whitespace before `pos` on its line in `content`, or `None` if
+/// there is anything else before `pos` on the line.
+fn line_indent(content: &str, pos: usize) -> Option<&str> {
+    let line_start = content[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let indent = &content[line_start..pos];
+    indent
+        .chars()
+        .all(|c| c == ' ' || c == '\t')
+        .then_some(indent)
+}
+
+/// Prefixes all non-empty lines of `text` but the first with `indent`.
+fn indent_lines(text: &str, indent: &str) -> String {
+    let mut lines = text.split('\n');
+    let mut res = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        res.push('\n');
+        if !line.trim().is_empty() {
+            res += indent;
+        }
+        res += line;
+    }
+    res
+}
+
+/// Pushes the template `name` onto the `chain` of currently applied templates.
+/// Fails if the template is already part of the chain or if the chain would
+/// exceed `max_depth`.
+fn enter_template(chain: &mut Vec<String>, name: &str, max_depth: usize) -> Result<()> {
+    if let Some(i) = chain.iter().position(|v| v == name) {
+        let cycle = chain[i..]
+            .iter()
+            .map(|v| v.as_str())
+            .chain([name])
+            .collect::<Vec<_>>();
+        return Err(Error::TemplateCycle(cycle.join(" -> ")));
+    }
+
+    if chain.len() >= max_depth {
+        return Err(Error::MaxDepthExceeded(max_depth, chain.join(" -> ")));
+    }
+
+    chain.push(name.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_check_target_dir {
+    use super::*;
+
+    fn check(source_dir: &Path, target_dir: &Path) -> Result<()> {
+        Builder::new(source_dir, target_dir, SiteConfig::default()).check_target_dir()
+    }
+
+    #[test]
+    fn general() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("src");
+        fs::create_dir_all(source_dir.join("pages")).unwrap();
+
+        assert!(check(&source_dir, &dir.path().join("dist")).is_ok());
+        for target_dir in [
+            dir.path().to_path_buf(),
+            source_dir.clone(),
+            source_dir.join("pages/out"),
+            source_dir.join("out"),
+            source_dir.join("pages/../out"),
+        ] {
+            assert!(
+                matches!(
+                    check(&source_dir, &target_dir),
+                    Err(Error::UnsafeOutputDir(..))
+                ),
+                "{target_dir:?}"
+            );
+        }
+
+        let other = dir.path().join("other");
+        fs::create_dir(&other).unwrap();
+        fs::write(other.join("a.txt"), "a").unwrap();
+        assert!(check(&source_dir, &other).is_err());
+        File::create(other.join(OUTPUT_MARKER_FILE)).unwrap();
+        assert!(check(&source_dir, &other).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_replace_target_dir {
+    use super::*;
+
+    #[test]
+    fn general() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_dir = dir.path().join("dist");
+        let staging_dir = dir.path().join(".dist.tmp");
+        let builder = Builder::new(dir.path().join("src"), &target_dir, SiteConfig::default());
+
+        fs::create_dir(&target_dir).unwrap();
+        fs::write(target_dir.join("a.html"), "old").unwrap();
+        fs::create_dir(&staging_dir).unwrap();
+        fs::write(staging_dir.join("a.html"), "new").unwrap();
+        builder.replace_target_dir(&staging_dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(target_dir.join("a.html")).unwrap(),
+            "new"
+        );
+        assert!(!staging_dir.exists());
+        assert!(!dir.path().join(".dist.old").exists());
+
+        // The previous output is restored if the staging directory can not
+        // be moved.
+        assert!(builder.replace_target_dir(&staging_dir).is_err());
+        assert_eq!(
+            fs::read_to_string(target_dir.join("a.html")).unwrap(),
+            "new"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_exec_output {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("src");
+        fs::create_dir_all(source_dir.join("pages")).unwrap();
+        fs::create_dir_all(source_dir.join("public")).unwrap();
+        fs::write(
+            source_dir.join("pages/index.html"),
+            "{{ exec printf '<b>' }} {{ exec printf '<i>' | safe }} {{ exec printf '<a>' | raw }}",
+        )
+        .unwrap();
+
+        let target_dir = dir.path().join("dist");
+        Builder::new(&source_dir, &target_dir, SiteConfig::default())
+            .build()
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(target_dir.join("index/index.html")).unwrap(),
+            "&lt;b&gt; <i> <a>"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_template_cache {
+    use super::*;
+
+    #[test]
+    fn cleared_by_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("src");
+        fs::create_dir_all(source_dir.join("pages")).unwrap();
+        fs::create_dir_all(source_dir.join("templates")).unwrap();
+        fs::create_dir_all(source_dir.join("public")).unwrap();
+        fs::write(source_dir.join("pages/index.html"), "{{ use h }}").unwrap();
+        fs::write(source_dir.join("templates/h.html"), "old").unwrap();
+
+        let target_dir = dir.path().join("dist");
+        let builder = Builder::new(&source_dir, &target_dir, SiteConfig::default());
+        let output = || fs::read_to_string(target_dir.join("index/index.html")).unwrap();
+        builder.build().unwrap();
+        assert_eq!(output(), "old");
+
+        fs::write(source_dir.join("templates/h.html"), "new").unwrap();
+        builder.build().unwrap();
+        assert_eq!(output(), "new");
+    }
+}
+
+#[cfg(test)]
+mod test_rebuild {
+    use super::*;
+
+    #[test]
+    fn deleted_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("src");
+        fs::create_dir_all(source_dir.join("pages/post")).unwrap();
+        fs::create_dir_all(source_dir.join("public")).unwrap();
+        fs::write(source_dir.join("pages/index.html"), "index").unwrap();
+        fs::write(source_dir.join("pages/post/index.html"), "post").unwrap();
+        fs::write(source_dir.join("pages/post/diagram.png"), "").unwrap();
+
+        let target_dir = dir.path().join("dist");
+        let builder = Builder::new(&source_dir, &target_dir, SiteConfig::default());
+        builder.build().unwrap();
+        assert!(target_dir.join("post/diagram.png").exists());
+
+        fs::remove_dir_all(source_dir.join("pages/post")).unwrap();
+        builder.rebuild(&[source_dir.join("pages/post")]).unwrap();
+        assert!(!target_dir.join("post").exists());
+        assert!(target_dir.join("index/index.html").exists());
+    }
+}
+
+#[cfg(test)]
+mod test_try_map_parallel {
+    use super::*;
+
+    #[test]
+    fn general() {
+        let sum = AtomicUsize::new(0);
+        let items: Vec<_> = (1..=100).collect();
+        let res = try_map_parallel(&items, 4, |v| {
+            sum.fetch_add(*v, Ordering::SeqCst);
+            Ok(v * 2)
+        });
+        assert_eq!(
+            res.unwrap(),
+            items.iter().map(|v| v * 2).collect::<Vec<_>>()
+        );
+        assert_eq!(sum.load(Ordering::SeqCst), 5050);
+    }
+
+    #[test]
+    fn first_error() {
+        let items: Vec<_> = (0..100).collect();
+        for _ in 0..10 {
+            let res = try_map_parallel(&items, 8, |v| match v {
+                10 | 11 | 50 => Err(Error::UnknownTemplate(v.to_string())),
+                _ => Ok(()),
+            });
+            assert!(matches!(res, Err(Error::UnknownTemplate(v)) if v == "10"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_template_file_name {
+    use super::*;
+
+    #[test]
+    fn general() {
+        assert_eq!(template_file_name("base").unwrap(), Path::new("base.html"));
+        assert_eq!(
+            template_file_name("components/card").unwrap(),
+            Path::new("components/card.html")
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for name in [
+            "",
+            "../secret",
+            "components/../../secret",
+            "/etc/passwd",
+            "./base",
+        ] {
+            assert!(
+                matches!(template_file_name(name), Err(Error::InvalidTemplateName(v)) if v == name),
+                "{name}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_enter_template {
+    use super::*;
+
+    #[test]
+    fn general() {
+        let mut chain = vec![];
+        assert!(enter_template(&mut chain, "a", 10).is_ok());
+        assert!(enter_template(&mut chain, "b", 10).is_ok());
+        assert_eq!(chain, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn cycle() {
+        let mut chain = vec!["x".to_string(), "a".to_string(), "b".to_string()];
+        assert!(matches!(
+            enter_template(&mut chain, "a", 10),
+            Err(Error::TemplateCycle(v)) if v == "a -> b -> a"
+        ));
+
+        let mut chain = vec!["a".to_string()];
+        assert!(matches!(
+            enter_template(&mut chain, "a", 10),
+            Err(Error::TemplateCycle(v)) if v == "a -> a"
+        ));
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut chain = vec!["a".to_string(), "b".to_string()];
+        assert!(matches!(
+            enter_template(&mut chain, "c", 2),
+            Err(Error::MaxDepthExceeded(2, v)) if v == "a -> b"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test_indent {
+    use super::*;
+
+    #[test]
+    fn general() {
+        let content = "<nav>\n    {{ use menu }}\n</nav>";
+        let pos = content.find("{{").unwrap();
+        assert_eq!(line_indent(content, pos), Some("    "));
+        assert_eq!(line_indent("<nav>{{ use menu }}", 5), None);
+        assert_eq!(line_indent("{{ use menu }}", 0), Some(""));
+
+        assert_eq!(
+            indent_lines("<ul>\n  <li>a</li>\n\n</ul>", "    "),
+            "<ul>\n      <li>a</li>\n\n    </ul>"
+        );
+    }
+}
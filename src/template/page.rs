@@ -1,52 +1,223 @@
 use super::errors::{Error, Result};
+use super::value;
 use serde::Deserialize;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Page {
     pub name: String,
+    /// Unique id of the page, which is the path of its source file, followed
+    /// by the index of the record for pages generated from data.
+    pub id: PathBuf,
+    /// Path of the page's source file.
+    pub source: PathBuf,
     pub content: String,
     pub config: Option<Config>,
+    /// All values of the page config, including ones unknown to temple.
+    pub front_matter: toml::Table,
+    /// Record of the data file the page has been generated from.
+    pub record: Option<serde_json::Value>,
+    /// Directory of the page bundle the page is the index page of. All other
+    /// files in the directory are copied next to the page's output.
+    pub bundle: Option<PathBuf>,
 }
 
+/// Names of the index page of a page bundle, in the order they are looked up.
+const BUNDLE_INDEX_FILES: [&str; 3] = ["index.html", "index.md", "index.markdown"];
+
 impl Page {
-    pub fn read(dir: impl AsRef<Path>) -> Result<Self> {
+    /// Reads the page file at `path`, or the index page of the page bundle
+    /// if `path` is a directory.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let (source, bundle) = match path.is_dir() {
+            true => {
+                let index = BUNDLE_INDEX_FILES
+                    .iter()
+                    .map(|f| path.join(f))
+                    .find(|p| p.is_file())
+                    .ok_or_else(|| Error::MissingBundleIndex(path.to_path_buf()))?;
+                (index, Some(path.to_path_buf()))
+            }
+            false => (path.to_path_buf(), None),
+        };
+
         let mut content = String::new();
-        File::open(&dir)?.read_to_string(&mut content)?;
+        File::open(&source)?.read_to_string(&mut content)?;
 
         let content = content.trim();
 
-        let (config, content) = match content.strip_prefix("+++") {
+        let (config, front_matter, content) = match content.strip_prefix("+++") {
             Some(content) => {
                 let Some(end_index) = content.find("+++") else {
                     return Err(Error::UnclosedConfig);
                 };
+                let raw = &content[..end_index];
+                let config = Config::parse(raw)?;
+                if let Some(output) = &config.output {
+                    check_output_path(output)?;
+                }
                 (
-                    Some(Config::parse(&content[..end_index])?),
+                    Some(config),
+                    toml::from_str(raw)?,
                     &content[end_index + 3..],
                 )
             }
-            None => (None, content),
+            None => (None, toml::Table::new(), content),
         };
 
         let name = config
             .as_ref()
             .and_then(|v| v.title.as_ref())
             .map(|v| v.to_string())
-            .unwrap_or_else(|| page_name_from_path(dir.as_ref()));
+            .unwrap_or_else(|| page_name_from_path(path, bundle.is_some()));
 
         Ok(Page {
             name,
+            id: source.clone(),
+            source,
             content: content.to_string(),
             config,
+            front_matter,
+            record: None,
+            bundle,
         })
     }
+
+    /// Returns one page for each of the given `records`. Placeholders like
+    /// `{slug}` in the configured `title`, `path` and `output` are replaced
+    /// with the values of the record. When no `output` is configured, it is
+    /// derived from the `path`.
+    pub fn generate(&self, records: &[serde_json::Value]) -> Result<Vec<Page>> {
+        let config = self.config.as_ref().expect("page config");
+
+        records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| {
+                let fill = |pattern: &Option<String>| {
+                    pattern
+                        .as_deref()
+                        .map(|p| fill_pattern(p, record))
+                        .transpose()
+                };
+
+                let title = fill(&config.title)?;
+                let path = fill(&config.path)?;
+                let output = match fill(&config.output)? {
+                    Some(output) => Some(output),
+                    None => path.as_deref().map(output_from_path),
+                };
+
+                if let Some(output) = &output {
+                    check_output_path(output)?;
+                }
+
+                let mut id = self.id.clone().into_os_string();
+                id.push(format!("#{i}"));
+
+                // The page values show the filled in placeholders as well.
+                let mut front_matter = self.front_matter.clone();
+                for (key, v) in [("title", &title), ("path", &path), ("output", &output)] {
+                    if let (Some(entry), Some(v)) = (front_matter.get_mut(key), v) {
+                        *entry = toml::Value::String(v.clone());
+                    }
+                }
+
+                Ok(Page {
+                    name: title.clone().unwrap_or_else(|| self.name.clone()),
+                    id: id.into(),
+                    source: self.source.clone(),
+                    content: self.content.clone(),
+                    config: Some(Config {
+                        title,
+                        path,
+                        output,
+                        ..config.clone()
+                    }),
+                    front_matter,
+                    record: Some(record.clone()),
+                    bundle: self.bundle.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the navigation path of the page, which is either the configured
+    /// `path` or `/<name>`.
+    pub fn path(&self) -> String {
+        self.config
+            .as_ref()
+            .and_then(|c| c.path.clone())
+            .unwrap_or_else(|| format!("/{}", self.name))
+    }
+
+    /// Returns the variables of the page accessible as `page.<key>` in
+    /// templates: all values of the page config as well as the page's `name`
+    /// and navigation `path`.
+    pub fn vars(&self) -> serde_json::Value {
+        let mut vars = serde_json::Map::new();
+        for (key, v) in &self.front_matter {
+            vars.insert(key.clone(), value::from_toml(v.clone()));
+        }
+        vars.insert("name".into(), self.name.clone().into());
+        vars.insert("path".into(), self.path().into());
+        vars.into()
+    }
 }
 
-fn page_name_from_path(dir: &Path) -> String {
-    let filename = dir.file_name().expect("file name").to_string_lossy();
+/// Replaces all placeholders like `{slug}` or `{author.name}` in `pattern`
+/// with the values at the given paths in `record`.
+fn fill_pattern(pattern: &str, record: &serde_json::Value) -> Result<String> {
+    let mut res = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| Error::InvalidPattern(pattern.to_string()))?;
+        let key = &rest[start + 1..start + end];
+        let v = value::lookup(record, key)
+            .ok_or_else(|| Error::UndefinedVariable(format!("record.{key}")))?;
+        res += &rest[..start];
+        res += &value::to_string(v);
+        rest = &rest[start + end + 1..];
+    }
+    res += rest;
+    Ok(res)
+}
+
+/// Fails unless the configured `output` path stays within the output
+/// directory, so that it contains neither absolute paths nor `..`.
+fn check_output_path(output: &str) -> Result<()> {
+    let valid = Path::new(output)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !valid {
+        return Err(Error::InvalidOutputPath(output.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns the output path of a page with the given navigation `path`, e.g.
+/// `products/foo/index.html` for `/products/foo/`.
+fn output_from_path(path: &str) -> String {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        "index.html".to_string()
+    } else if path.ends_with(".html") {
+        path.to_string()
+    } else {
+        format!("{path}/index.html")
+    }
+}
+
+/// Returns the name of the page at `path`, which is the name of the file
+/// without the sorting prefix and extension, or the name of the directory
+/// without the sorting prefix for page bundles.
+fn page_name_from_path(path: &Path, is_bundle: bool) -> String {
+    let filename = path.file_name().expect("file name").to_string_lossy();
 
     let name = match filename.find('_') {
         Some(i) => &filename[i + 1..],
@@ -54,20 +225,23 @@ fn page_name_from_path(dir: &Path) -> String {
     };
 
     let name = match name.rfind('.') {
-        Some(i) => &name[..i],
-        None => name,
+        Some(i) if !is_bundle => &name[..i],
+        _ => name,
     };
 
     name.to_string()
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct Config {
     pub title: Option<String>,
     pub path: Option<String>,
     pub output: Option<String>,
     #[serde(default)]
     pub navignore: bool,
+    /// Path of a data file, relative to the source directory, containing a
+    /// list of records. One page is generated for each record.
+    pub generate_from: Option<String>,
 }
 
 impl Config {
@@ -75,3 +249,131 @@ impl Config {
         Ok(toml::from_str(content.as_ref())?)
     }
 }
+
+#[cfg(test)]
+mod test_generate {
+    use super::*;
+    use serde_json::json;
+
+    fn page(config: &str) -> Page {
+        let front_matter: toml::Table = toml::from_str(config).unwrap();
+        Page {
+            name: "product".into(),
+            id: "pages/product.html".into(),
+            source: "pages/product.html".into(),
+            content: "content".into(),
+            config: Some(Config::parse(config).unwrap()),
+            front_matter,
+            record: None,
+            bundle: None,
+        }
+    }
+
+    #[test]
+    fn general() {
+        let page = page(
+            r#"
+            title = "{name}"
+            path = "/products/{slug}/"
+            generate_from = "data/products.json"
+            "#,
+        );
+        let records = [
+            json!({ "slug": "a", "name": "A" }),
+            json!({ "slug": "b", "name": "B" }),
+        ];
+        let pages = page.generate(&records).unwrap();
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].name, "B");
+        assert_eq!(pages[1].id, Path::new("pages/product.html#1"));
+        assert_eq!(pages[1].source, Path::new("pages/product.html"));
+        assert_eq!(pages[1].path(), "/products/b/");
+        let config = pages[1].config.as_ref().unwrap();
+        assert_eq!(config.output.as_deref(), Some("products/b/index.html"));
+        assert_eq!(pages[1].record, Some(records[1].clone()));
+        assert_eq!(pages[1].vars()["title"], json!("B"));
+        assert_eq!(pages[1].vars()["path"], json!("/products/b/"));
+    }
+
+    #[test]
+    fn invalid() {
+        let page = page(r#"output = "{slug}.html""#);
+
+        assert!(matches!(
+            page.generate(&[json!({ "name": "A" })]),
+            Err(Error::UndefinedVariable(v)) if v == "record.slug"
+        ));
+
+        assert!(matches!(
+            page.generate(&[json!({ "slug": "../../etc/passwd" })]),
+            Err(Error::InvalidOutputPath(_))
+        ));
+    }
+
+    #[test]
+    fn patterns() {
+        let record = json!({ "a": "x", "b": { "c": 1 } });
+        assert_eq!(fill_pattern("/{a}/{b.c}/", &record).unwrap(), "/x/1/");
+        assert_eq!(fill_pattern("none", &record).unwrap(), "none");
+        assert!(matches!(
+            fill_pattern("/{a", &record),
+            Err(Error::InvalidPattern(_))
+        ));
+
+        assert_eq!(output_from_path("/"), "index.html");
+        assert_eq!(output_from_path("/a/b/"), "a/b/index.html");
+        assert_eq!(output_from_path("/a/b.html"), "a/b.html");
+    }
+}
+
+#[cfg(test)]
+mod test_read {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn bundles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("1_post")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::create_dir_all(dir.join("2_v1.2-release")).unwrap();
+        fs::write(dir.join("2_v1.2-release/index.html"), "").unwrap();
+        fs::write(dir.join("1_post/index.md"), "# Post").unwrap();
+        fs::write(dir.join("1_post/diagram.png"), "").unwrap();
+
+        let page = Page::read(dir.join("1_post")).unwrap();
+        assert_eq!(page.name, "post");
+        assert_eq!(page.source, dir.join("1_post/index.md"));
+        assert_eq!(page.bundle, Some(dir.join("1_post")));
+        assert_eq!(page.content, "# Post");
+        assert_eq!(
+            Page::read(dir.join("2_v1.2-release")).unwrap().name,
+            "v1.2-release"
+        );
+        assert!(matches!(
+            Page::read(dir.join("empty")),
+            Err(Error::MissingBundleIndex(d)) if d == dir.join("empty")
+        ));
+    }
+
+    #[test]
+    fn output_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let read = |output: &str| {
+            let path = tmp.path().join("page.html");
+            fs::write(&path, format!("+++\noutput = \"{output}\"\n+++\nx")).unwrap();
+            Page::read(path)
+        };
+
+        assert!(read("index.html").is_ok());
+        assert!(read("./blog/{slug}.html").is_ok());
+        for output in ["/tmp/x.html", "../x.html", "blog/../../x.html"] {
+            assert!(
+                matches!(read(output), Err(Error::InvalidOutputPath(v)) if v == output),
+                "{output}"
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use super::errors::{Error, Result};
+use super::exec::parse_timeout;
+use std::time::Duration;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Template<'a> {
     Extends {
         name: &'a str,
@@ -8,53 +10,126 @@ pub enum Template<'a> {
     Use {
         name: &'a str,
     },
+    Call {
+        name: &'a str,
+        args: Vec<(&'a str, &'a str)>,
+        /// Whether the template opens a block closed by 'endcall'.
+        block: bool,
+    },
+    EndCall,
+    Body,
     PageName,
     NavItems,
+    Toc {
+        min: u8,
+        max: u8,
+    },
     CurrentDate {
         format: Option<String>,
     },
+    Date {
+        value: &'a str,
+        format: Option<String>,
+    },
+    LastModified {
+        format: Option<String>,
+    },
     Exec {
+        options: ExecOptions,
         command: &'a str,
         args: Vec<&'a str>,
+        filters: Vec<Filter<'a>>,
+        /// Whether the template opens a block closed by 'endexec'.
+        block: bool,
+    },
+    EndExec,
+    For {
+        name: &'a str,
+        value: &'a str,
+    },
+    EndFor,
+    Var {
+        path: &'a str,
+        filters: Vec<Filter<'a>>,
     },
     PageContent,
 }
 
+/// A filter applied to the value of a variable or the output of a command,
+/// given after a `|` like `{{ page.title | safe }}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter<'a> {
+    pub name: &'a str,
+    pub args: Vec<&'a str>,
+}
+
+/// Options of an 'exec' template, given as `key=value` pairs before the
+/// command.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExecOptions {
+    /// Overrides the globally configured timeout of the command.
+    pub timeout: Option<Duration>,
+    /// Runs the command on every use instead of reusing the output of an
+    /// identical command.
+    pub nocache: bool,
+    /// Reuses the output of an identical command only on the same page, for
+    /// commands whose output depends on the page's environment variables.
+    pub perpage: bool,
+}
+
 impl Template<'_> {
     pub fn id(&self) -> &'static str {
         match &self {
             Self::Extends { name: _ } => "extends",
             Self::Use { name: _ } => "use",
+            Self::Call { .. } => "call",
+            Self::EndCall => "endcall",
+            Self::Body => "body",
             Self::PageName => "pagename",
             Self::NavItems => "navitems",
+            Self::Toc { .. } => "toc",
             Self::CurrentDate { format: _ } => "currentdate",
-            Self::Exec {
-                command: _,
-                args: _,
-            } => "exec",
+            Self::Date { .. } => "date",
+            Self::LastModified { format: _ } => "lastmodified",
+            Self::Exec { .. } => "exec",
+            Self::EndExec => "endexec",
+            Self::For { .. } => "for",
+            Self::EndFor => "endfor",
+            Self::Var { .. } => "var",
             Self::PageContent => "pagecontent",
         }
     }
+
+    /// Returns whether the template opens a block, which is either a `for` or
+    /// a `call` or `exec` marked with a trailing `:`.
+    pub fn opens_block(&self) -> bool {
+        match self {
+            Self::For { .. } => true,
+            Self::Call { block, .. } | Self::Exec { block, .. } => *block,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TemplateInstance<'a> {
     pub start_pos: usize,
     pub end_pos: usize,
     pub template: Template<'a>,
 }
 
-impl TemplateInstance<'_> {
-    pub fn insert_between(&self, content: &str, insert: &str) -> String {
-        content[..self.start_pos].to_string() + insert + &content[self.end_pos + 1..]
-    }
-
-    pub fn remove_between(&self, content: &str) -> String {
-        content[..self.start_pos].to_string() + &content[self.end_pos + 1..]
-    }
+#[cfg(test)]
+pub fn find_next_template(content: &str) -> Result<Option<TemplateInstance<'_>>> {
+    find_next_template_with(content, &|_| false)
 }
 
-pub fn find_next_template(content: &str) -> Result<Option<TemplateInstance<'_>>> {
+/// Like [`find_next_template`], but templates consisting of a single name for
+/// which `is_var` returns `true` are taken as variables. Dotted paths like
+/// `page.title` are always taken as variables.
+pub fn find_next_template_with<'a>(
+    content: &'a str,
+    is_var: &dyn Fn(&str) -> bool,
+) -> Result<Option<TemplateInstance<'a>>> {
     let Some(start_pos) = content.find("{{") else {
         return Ok(None);
     };
@@ -69,7 +144,10 @@ pub fn find_next_template(content: &str) -> Result<Option<TemplateInstance<'_>>>
 
     let end_pos = start_pos + end_pos_rel + 1;
 
-    let template = parse_template(&content[start_pos + 2..end_pos - 1])?;
+    let inner = &content[start_pos + 2..end_pos - 1];
+    let inner = inner.strip_prefix('-').unwrap_or(inner);
+    let inner = inner.strip_suffix('-').unwrap_or(inner);
+    let template = parse_template_with(inner, is_var)?;
 
     Ok(Some(TemplateInstance {
         start_pos,
@@ -78,42 +156,345 @@ pub fn find_next_template(content: &str) -> Result<Option<TemplateInstance<'_>>>
     }))
 }
 
-pub fn find_template<'a>(
-    content: &'a str,
-    target_id: &'_ str,
-) -> Result<Option<TemplateInstance<'a>>> {
+/// Removes the trim markers of all templates in `content` along with the
+/// whitespace they trim: `{{-` removes all whitespace before the template and
+/// `-}}` all whitespace after it. Returns `None` if there are no trim markers.
+pub fn apply_trim_markers(content: &str) -> Option<String> {
+    if !content.contains("{{-") && !content.contains("-}}") {
+        return None;
+    }
+
+    let mut res = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let inner = &rest[start + 2..start + len];
+        let (before, inner) = match inner.strip_prefix('-') {
+            Some(inner) => (rest[..start].trim_end(), inner),
+            None => (&rest[..start], inner),
+        };
+        let (inner, trim_after) = match inner.strip_suffix('-') {
+            Some(inner) => (inner, true),
+            None => (inner, false),
+        };
+
+        res += before;
+        res += "{{";
+        res += inner;
+        res += "}}";
+        rest = &rest[start + len + 2..];
+        if trim_after {
+            rest = rest.trim_start();
+        }
+    }
+    res += rest;
+
+    Some(res)
+}
+
+/// A part of the content of a page or template: either text or a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    Template(TemplateInstance<'a>),
+}
+
+impl<'a> Piece<'a> {
+    pub fn template(&self) -> Option<&Template<'a>> {
+        match self {
+            Self::Text(_) => None,
+            Self::Template(t) => Some(&t.template),
+        }
+    }
+}
+
+pub type Pieces<'a> = Vec<Piece<'a>>;
+
+self_cell::self_cell!(
+    /// The content of a page or template file split into text and templates,
+    /// so that a template file is parsed only once and not for every page
+    /// using it.
+    pub struct ParsedTemplate {
+        owner: String,
+        #[covariant]
+        dependent: Pieces,
+    }
+
+    impl {Debug}
+);
+
+impl ParsedTemplate {
+    /// Parses `content` after removing whitespace at its start and end and
+    /// applying trim markers. As variables are not known before rendering,
+    /// all single names which are not templates are taken as variables.
+    pub fn parse(content: &str) -> Result<Self> {
+        let content = content.trim();
+        let content = apply_trim_markers(content).unwrap_or_else(|| content.to_string());
+        Self::try_new(content, |content| parse_pieces(content))
+    }
+
+    /// Returns the content the template has been parsed from.
+    pub fn text(&self) -> &str {
+        self.borrow_owner()
+    }
+
+    pub fn pieces(&self) -> &[Piece<'_>] {
+        self.borrow_dependent()
+    }
+}
+
+/// Splits `content` into text and templates.
+fn parse_pieces(content: &str) -> Result<Vec<Piece<'_>>> {
+    let mut pieces = vec![];
     let mut offset = 0;
+    while let Some(t) = find_next_template_with(&content[offset..], &|_| true)? {
+        if t.start_pos > 0 {
+            pieces.push(Piece::Text(&content[offset..offset + t.start_pos]));
+        }
+        pieces.push(Piece::Template(TemplateInstance {
+            start_pos: t.start_pos + offset,
+            end_pos: t.end_pos + offset,
+            template: t.template,
+        }));
+        offset += t.end_pos + 1;
+    }
+    if offset < content.len() {
+        pieces.push(Piece::Text(&content[offset..]));
+    }
+    Ok(pieces)
+}
 
-    while let Some(template) = find_next_template(&content[offset..])? {
-        if template.template.id() == target_id {
-            return Ok(Some(TemplateInstance {
-                start_pos: template.start_pos + offset,
-                end_pos: template.end_pos + offset,
-                template: template.template,
-            }));
+/// Returns the pieces without whitespace at the start and end of their text.
+pub fn trim_pieces<'a>(pieces: &[Piece<'a>]) -> Vec<Piece<'a>> {
+    let mut pieces = pieces.to_vec();
+    while let Some(Piece::Text(text)) = pieces.first_mut() {
+        *text = text.trim_start();
+        if !text.is_empty() {
+            break;
         }
-        offset += template.end_pos + 1;
+        pieces.remove(0);
     }
+    while let Some(Piece::Text(text)) = pieces.last_mut() {
+        *text = text.trim_end();
+        if !text.is_empty() {
+            break;
+        }
+        pieces.pop();
+    }
+    pieces
+}
+
+/// Returns the index of the first template with the id `target_id`.
+pub fn find_template(pieces: &[Piece], target_id: &str) -> Option<usize> {
+    pieces
+        .iter()
+        .position(|p| p.template().is_some_and(|t| t.id() == target_id))
+}
 
-    Ok(None)
+/// Returns the index of the template with the id `close_id` which closes a
+/// block opened right before the given `pieces`. Blocks opened by templates
+/// with the id `open_id` within `pieces` are skipped, so that blocks can be
+/// nested. Templates with this id which don't open a block, like a single line
+/// `exec`, are ignored.
+pub fn find_block_end(pieces: &[Piece], open_id: &str, close_id: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, piece) in pieces.iter().enumerate() {
+        let Some(template) = piece.template() else {
+            continue;
+        };
+        if template.id() == close_id {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        } else if template.id() == open_id && template.opens_block() {
+            depth += 1;
+        }
+    }
+    None
 }
 
+#[cfg(test)]
 fn parse_template(content: &str) -> Result<Template<'_>> {
+    parse_template_with(content, &|_| false)
+}
+
+fn parse_template_with<'a>(
+    content: &'a str,
+    is_var: &dyn Fn(&str) -> bool,
+) -> Result<Template<'a>> {
     let content = content.trim();
     if content.is_empty() {
         return Err(Error::Empty);
     }
 
+    // A trailing `:` marks a 'call' or 'exec' template as opening a block, so
+    // that it is never confused with a single line one.
+    let (content, block) = match content.strip_suffix(':') {
+        Some(rest) if rest.ends_with(char::is_whitespace) => (rest.trim_end(), true),
+        _ => (content, false),
+    };
+
+    let mut split = tokenize(content)?.into_iter();
+
+    let template: Result<_> = match split.next().expect("should not be empty") {
+        "extends" => Ok(Template::Extends {
+            name: split.next().ok_or(Error::MissingArgument("name"))?,
+        }),
+        "use" => Ok(Template::Use {
+            name: split.next().ok_or(Error::MissingArgument("name"))?,
+        }),
+        "call" => {
+            let name = split.next().ok_or(Error::MissingArgument("name"))?;
+            let args = split
+                .map(|arg| match arg.split_once('=') {
+                    Some((key, value)) if !key.is_empty() => Ok((key, unquote(value))),
+                    _ => Err(Error::UnexpectedArgument(arg.to_string())),
+                })
+                .collect::<Result<_>>()?;
+            return Ok(Template::Call { name, args, block });
+        }
+        "endcall" => Ok(Template::EndCall),
+        "body" => Ok(Template::Body),
+        "pagename" => Ok(Template::PageName),
+        "navitems" => Ok(Template::NavItems),
+        "toc" => {
+            let mut level = |default| match split.next() {
+                Some(v) => v
+                    .parse()
+                    .ok()
+                    .filter(|l| (1..=6).contains(l))
+                    .ok_or_else(|| Error::InvalidHeadingLevel(v.to_string())),
+                None => Ok(default),
+            };
+            let min = level(1)?;
+            let max = level(6)?;
+            if min > max {
+                return Err(Error::InvalidHeadingLevel(max.to_string()));
+            }
+            Ok(Template::Toc { min, max })
+        }
+        "currentdate" => Ok(Template::CurrentDate {
+            format: split.next().map(|v| v.to_owned()),
+        }),
+        "date" => Ok(Template::Date {
+            value: split.next().ok_or(Error::MissingArgument("value"))?,
+            format: split.next().map(|v| v.to_owned()),
+        }),
+        "lastmodified" => Ok(Template::LastModified {
+            format: split.next().map(|v| v.to_owned()),
+        }),
+        "exec" => {
+            // Filters are applied to the output of the command.
+            let mut segments = split_filters(content)?.into_iter();
+            let mut split = tokenize(segments.next().expect("should not be empty"))?.into_iter();
+            split.next();
+
+            let mut options = ExecOptions::default();
+            let command = loop {
+                let arg = split.next().ok_or(Error::MissingArgument("command"))?;
+                match arg.split_once('=') {
+                    None if arg == "nocache" => options.nocache = true,
+                    None if arg == "perpage" => options.perpage = true,
+                    Some(("timeout", v)) => {
+                        options.timeout = Some(
+                            parse_timeout(v)
+                                .ok_or_else(|| Error::InvalidExecOption(arg.to_string()))?,
+                        );
+                    }
+                    _ => break arg,
+                }
+            };
+            let args = split.collect();
+            return Ok(Template::Exec {
+                options,
+                command,
+                args,
+                filters: parse_filters(segments)?,
+                block,
+            });
+        }
+        "endexec" => Ok(Template::EndExec),
+        "for" => {
+            let name = split.next().ok_or(Error::MissingArgument("name"))?;
+            if split.next() != Some("in") {
+                return Err(Error::MissingArgument("in"));
+            }
+            let value = split.next().ok_or(Error::MissingArgument("value"))?;
+            Ok(Template::For { name, value })
+        }
+        "endfor" => Ok(Template::EndFor),
+        "pagecontent" => Ok(Template::PageContent),
+        _ if !block => return parse_var(content, is_var),
+        _ => Err(Error::UnexpectedArgument(":".to_string())),
+    };
+    let template = template?;
+
+    // Keywords take no filters or further arguments besides the ones parsed
+    // above, which would otherwise be silently ignored.
+    if block {
+        return Err(Error::UnexpectedArgument(":".to_string()));
+    }
+    if let Some(arg) = split.next() {
+        return Err(Error::UnexpectedArgument(arg.to_string()));
+    }
+    Ok(template)
+}
+
+/// Parses a variable with optional filters, like `page.title | safe`.
+fn parse_var<'a>(content: &'a str, is_var: &dyn Fn(&str) -> bool) -> Result<Template<'a>> {
+    let mut segments = split_filters(content)?.into_iter();
+    let mut split = tokenize(segments.next().expect("should not be empty"))?.into_iter();
+
+    let path = match split.next() {
+        Some(path) if is_var_path(path) && (path.contains('.') || is_var(path)) => path,
+        Some(name) => return Err(Error::UnknownTemplate(name.to_string())),
+        None => return Err(Error::MissingArgument("variable")),
+    };
+    if let Some(arg) = split.next() {
+        return Err(Error::UnexpectedArgument(arg.to_string()));
+    }
+
+    Ok(Template::Var {
+        path,
+        filters: parse_filters(segments)?,
+    })
+}
+
+/// Parses the filters given as `segments` after the first `|`.
+fn parse_filters<'a>(segments: impl Iterator<Item = &'a str>) -> Result<Vec<Filter<'a>>> {
+    segments
+        .map(|segment| {
+            let mut split = tokenize(segment)?.into_iter();
+            let name = split.next().ok_or(Error::MissingArgument("filter"))?;
+            Ok(Filter {
+                name,
+                args: split.collect(),
+            })
+        })
+        .collect()
+}
+
+/// Splits `content` into whitespace separated arguments. Arguments containing
+/// whitespace can be quoted with `"` or `'`. Quotes within an argument, like
+/// in `key="some value"`, are kept as part of the argument.
+fn tokenize(content: &str) -> Result<Vec<&str>> {
     let mut split = vec![];
     let mut active_quote = None;
+    // Whether the active quote started within an argument.
+    let mut inner_quote = false;
     let mut start = 0;
 
     for (i, c) in content.char_indices() {
         if let Some(quote_char) = active_quote {
             if quote_char == c {
                 active_quote = None;
-                split.push(&content[start..i]);
-                start = i + 1;
+                if !inner_quote {
+                    split.push(&content[start..i]);
+                    start = i + 1;
+                }
             }
             continue;
         }
@@ -126,7 +507,10 @@ fn parse_template(content: &str) -> Result<Template<'_>> {
                 start = i + 1;
             }
             '"' | '\'' => {
-                start = i + 1;
+                inner_quote = start != i;
+                if !inner_quote {
+                    start = i + 1;
+                }
                 active_quote = Some(c);
             }
             _ => {}
@@ -142,28 +526,53 @@ fn parse_template(content: &str) -> Result<Template<'_>> {
         split.push(rest);
     }
 
-    let mut split = split.into_iter();
+    Ok(split)
+}
 
-    match split.next().expect("should not be empty") {
-        "extends" => Ok(Template::Extends {
-            name: split.next().ok_or(Error::MissingArgument("name"))?,
-        }),
-        "use" => Ok(Template::Use {
-            name: split.next().ok_or(Error::MissingArgument("name"))?,
-        }),
-        "pagename" => Ok(Template::PageName),
-        "navitems" => Ok(Template::NavItems),
-        "currentdate" => Ok(Template::CurrentDate {
-            format: split.next().map(|v| v.to_owned()),
-        }),
-        "exec" => {
-            let command = split.next().ok_or(Error::MissingArgument("command"))?;
-            let args = split.collect();
-            Ok(Template::Exec { command, args })
+/// Removes the quotes around `value`, if it is quoted.
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+        .unwrap_or(value)
+}
+
+/// Splits `content` at each `|` which is not quoted.
+fn split_filters(content: &str) -> Result<Vec<&str>> {
+    let mut segments = vec![];
+    let mut active_quote = None;
+    let mut start = 0;
+
+    for (i, c) in content.char_indices() {
+        match (active_quote, c) {
+            (Some(quote_char), _) if quote_char == c => active_quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => active_quote = Some(c),
+            (None, '|') => {
+                segments.push(&content[start..i]);
+                start = i + 1;
+            }
+            _ => {}
         }
-        "pagecontent" => Ok(Template::PageContent),
-        name => Err(Error::UnknownTemplate(name.to_string())),
     }
+
+    if active_quote.is_some() {
+        return Err(Error::UnclosedQuote);
+    }
+    segments.push(&content[start..]);
+
+    Ok(segments)
+}
+
+/// Returns whether `path` is a valid variable path, which consists of names
+/// separated by dots.
+fn is_var_path(path: &str) -> bool {
+    path.split('.').all(|name| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
 }
 
 #[cfg(test)]
@@ -204,6 +613,15 @@ mod test_find_next_template {
             Ok(None)
         ));
 
+        assert!(matches!(
+            find_next_template("some content {{- extends foo -}} more content"),
+            Ok(Some(TemplateInstance {
+                start_pos: 13,
+                end_pos: 31,
+                template: Template::Extends { name: "foo" }
+            }))
+        ));
+
         assert!(matches!(
             find_next_template("some content {{ extends 'foo bar' more content"),
             Err(Error::UnclosedTemplate)
@@ -222,20 +640,113 @@ mod test_find_next_template {
 }
 
 #[cfg(test)]
-mod test_find_template {
+mod test_apply_trim_markers {
+    use super::*;
+
+    #[test]
+    fn general() {
+        assert_eq!(apply_trim_markers("a {{ b }} c"), None);
+        assert_eq!(
+            apply_trim_markers("a\n  {{- b -}}\n\n  c").as_deref(),
+            Some("a{{ b }}c")
+        );
+        assert_eq!(
+            apply_trim_markers(
+                "<ul>\n  {{- for x in y }}\n  <li>{{ x }}</li>\n  {{- endfor }}\n</ul>"
+            )
+            .as_deref(),
+            Some("<ul>{{ for x in y }}\n  <li>{{ x }}</li>{{ endfor }}\n</ul>")
+        );
+        assert_eq!(
+            apply_trim_markers("a {{ x | replace - + -}} b").as_deref(),
+            Some("a {{ x | replace - + }}b")
+        );
+        assert_eq!(apply_trim_markers("a {{- b").as_deref(), Some("a {{- b"));
+    }
+}
+
+#[cfg(test)]
+mod test_parsed_template {
     use super::*;
 
     #[test]
     fn general() {
+        let parsed = ParsedTemplate::parse("\n a {{ item }}\n  {{- pagename }} b \n").unwrap();
+        assert_eq!(parsed.text(), "a {{ item }}{{ pagename }} b");
+        assert_eq!(
+            parsed.pieces(),
+            [
+                Piece::Text("a "),
+                Piece::Template(TemplateInstance {
+                    start_pos: 2,
+                    end_pos: 11,
+                    template: Template::Var {
+                        path: "item",
+                        filters: vec![]
+                    }
+                }),
+                Piece::Template(TemplateInstance {
+                    start_pos: 12,
+                    end_pos: 25,
+                    template: Template::PageName
+                }),
+                Piece::Text(" b"),
+            ]
+        );
+
         assert!(matches!(
-            find_template("a {{ pagename }} b {{ pagecontent }} c", "pagecontent"),
-            Ok(Some(TemplateInstance {
-                start_pos: 19,
-                end_pos: 35,
-                template: Template::PageContent
-            }))
+            ParsedTemplate::parse("a {{ pagename"),
+            Err(Error::UnclosedTemplate)
         ));
     }
+
+    #[test]
+    fn trim() {
+        let parsed = ParsedTemplate::parse("{{ pagename }}").unwrap();
+        let pieces = [
+            Piece::Text(" \n "),
+            parsed.pieces()[0].clone(),
+            Piece::Text(" a "),
+        ];
+        assert_eq!(
+            trim_pieces(&pieces),
+            [parsed.pieces()[0].clone(), Piece::Text(" a")]
+        );
+        assert_eq!(trim_pieces(&[Piece::Text("  ")]), []);
+    }
+}
+
+#[cfg(test)]
+mod test_find_template {
+    use super::*;
+
+    #[test]
+    fn general() {
+        let parsed = ParsedTemplate::parse("a {{ pagename }} b {{ pagecontent }} c").unwrap();
+        assert_eq!(find_template(parsed.pieces(), "pagecontent"), Some(3));
+        assert_eq!(find_template(parsed.pieces(), "extends"), None);
+    }
+}
+
+#[cfg(test)]
+mod test_find_block_end {
+    use super::*;
+
+    fn block_end(content: &str) -> Option<usize> {
+        let parsed = ParsedTemplate::parse(content).unwrap();
+        find_block_end(parsed.pieces(), "exec", "endexec")
+    }
+
+    #[test]
+    fn general() {
+        assert_eq!(block_end("a {{ endexec }} b"), Some(1));
+        assert_eq!(
+            block_end("{{ exec a : }} {{ endexec }} {{ endexec }}"),
+            Some(4)
+        );
+        assert_eq!(block_end("{{ exec a }} {{ endexec }}"), Some(2));
+        assert_eq!(block_end("a {{ pagename }} b"), None);
+    }
 }
 
 #[cfg(test)]
@@ -280,6 +791,25 @@ mod test_template_parse {
             parse_template("thisdoesnotexist"),
             Err(Error::UnknownTemplate(v)) if &v == "thisdoesnotexist"
         ));
+
+        for content in [
+            "pagename | upper",
+            "navitems x",
+            "body x",
+            "pagecontent | safe",
+            "endfor x",
+            "endcall x",
+            "endexec x",
+            "extends foo bar",
+            "for x in y z",
+            "toc 1 2 3",
+            "date page.date '%Y' x",
+        ] {
+            assert!(
+                matches!(parse_template(content), Err(Error::UnexpectedArgument(_))),
+                "{content}"
+            );
+        }
     }
 
     #[test]
@@ -308,6 +838,37 @@ mod test_template_parse {
         ));
     }
 
+    #[test]
+    fn call() {
+        let res = parse_template(r#"call alert type="warning" title='Look out!' x=1"#);
+        match res.unwrap() {
+            Template::Call { name, args, block } => {
+                assert_eq!(name, "alert");
+                assert_eq!(
+                    args,
+                    vec![("type", "warning"), ("title", "Look out!"), ("x", "1")]
+                );
+                assert!(!block);
+            }
+            _ => panic!("invliad template"),
+        }
+        assert!(matches!(
+            parse_template("call alert type=info :"),
+            Ok(Template::Call { block: true, args, .. }) if args == vec![("type", "info")]
+        ));
+
+        assert!(matches!(
+            parse_template("call"),
+            Err(Error::MissingArgument("name"))
+        ));
+        assert!(matches!(
+            parse_template("call alert warning"),
+            Err(Error::UnexpectedArgument(v)) if v == "warning"
+        ));
+        assert!(matches!(parse_template("endcall"), Ok(Template::EndCall)));
+        assert!(matches!(parse_template("body"), Ok(Template::Body)));
+    }
+
     #[test]
     fn pagename() {
         assert!(matches!(parse_template("pagename"), Ok(Template::PageName)));
@@ -318,6 +879,30 @@ mod test_template_parse {
         assert!(matches!(parse_template("navitems"), Ok(Template::NavItems)));
     }
 
+    #[test]
+    fn toc() {
+        assert_eq!(
+            parse_template("toc").ok(),
+            Some(Template::Toc { min: 1, max: 6 })
+        );
+        assert_eq!(
+            parse_template("toc 2").ok(),
+            Some(Template::Toc { min: 2, max: 6 })
+        );
+        assert_eq!(
+            parse_template("toc 2 3").ok(),
+            Some(Template::Toc { min: 2, max: 3 })
+        );
+        assert!(matches!(
+            parse_template("toc 0"),
+            Err(Error::InvalidHeadingLevel(v)) if v == "0"
+        ));
+        assert!(matches!(
+            parse_template("toc 3 2"),
+            Err(Error::InvalidHeadingLevel(v)) if v == "2"
+        ));
+    }
+
     #[test]
     fn currentdate() {
         assert!(matches!(
@@ -331,29 +916,268 @@ mod test_template_parse {
         ));
     }
 
+    #[test]
+    fn date() {
+        assert!(matches!(
+            parse_template(r#"date page.date "%d %B %Y""#),
+            Ok(Template::Date { value: "page.date", format: Some(f) }) if &f == "%d %B %Y"
+        ));
+
+        assert!(matches!(
+            parse_template("date page.date"),
+            Ok(Template::Date {
+                value: "page.date",
+                format: None
+            })
+        ));
+
+        assert!(matches!(
+            parse_template("date"),
+            Err(Error::MissingArgument("value"))
+        ));
+    }
+
+    #[test]
+    fn lastmodified() {
+        assert!(matches!(
+            parse_template("lastmodified"),
+            Ok(Template::LastModified { format: None })
+        ));
+
+        assert!(matches!(
+            parse_template("lastmodified '%Y'"),
+            Ok(Template::LastModified { format: Some(f) }) if &f == "%Y"
+        ));
+    }
+
+    #[test]
+    fn r#for() {
+        assert!(matches!(
+            parse_template("for member in data.team.members"),
+            Ok(Template::For {
+                name: "member",
+                value: "data.team.members"
+            })
+        ));
+
+        assert!(matches!(
+            parse_template("for member data.team"),
+            Err(Error::MissingArgument("in"))
+        ));
+
+        assert!(matches!(
+            parse_template("for member in"),
+            Err(Error::MissingArgument("value"))
+        ));
+
+        assert!(matches!(parse_template("endfor"), Ok(Template::EndFor)));
+    }
+
+    #[test]
+    fn var() {
+        assert!(matches!(
+            parse_template("page.title"),
+            Ok(Template::Var { path: "page.title", filters }) if filters.is_empty()
+        ));
+
+        assert!(matches!(
+            parse_template(" data.team.members.0.name "),
+            Ok(Template::Var {
+                path: "data.team.members.0.name",
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            parse_template_with("member", &|name| name == "member"),
+            Ok(Template::Var { path: "member", .. })
+        ));
+
+        assert!(matches!(
+            parse_template("member"),
+            Err(Error::UnknownTemplate(v)) if v == "member"
+        ));
+
+        assert!(matches!(
+            parse_template("page..title"),
+            Err(Error::UnknownTemplate(_))
+        ));
+
+        assert!(matches!(
+            parse_template("page.title foo"),
+            Err(Error::UnexpectedArgument(v)) if v == "foo"
+        ));
+    }
+
+    #[test]
+    fn var_filters() {
+        let res = parse_template(r#"page.title | safe|replace "a | b" 'c' "#);
+        match res.unwrap() {
+            Template::Var { path, filters } => {
+                assert_eq!(path, "page.title");
+                assert_eq!(
+                    filters,
+                    vec![
+                        Filter {
+                            name: "safe",
+                            args: vec![]
+                        },
+                        Filter {
+                            name: "replace",
+                            args: vec!["a | b", "c"]
+                        },
+                    ]
+                );
+            }
+            _ => panic!("invliad template"),
+        }
+
+        assert!(matches!(
+            parse_template("page.title|safe"),
+            Ok(Template::Var { path: "page.title", filters }) if filters.len() == 1
+        ));
+
+        assert!(matches!(
+            parse_template("page.title | "),
+            Err(Error::MissingArgument("filter"))
+        ));
+
+        assert!(matches!(
+            parse_template("| safe"),
+            Err(Error::MissingArgument("variable"))
+        ));
+
+        assert!(matches!(
+            parse_template("page.title | 'safe"),
+            Err(Error::UnclosedQuote)
+        ));
+    }
+
     #[test]
     fn exec() {
         let res = parse_template("exec ls");
         match res.unwrap() {
-            Template::Exec { command, args } => {
+            Template::Exec {
+                options,
+                command,
+                args,
+                filters,
+                block,
+            } => {
+                assert_eq!(options, ExecOptions::default());
                 assert_eq!(command, "ls");
                 assert!(args.is_empty());
+                assert!(filters.is_empty());
+                assert!(!block);
             }
             _ => panic!("invliad template"),
         }
 
         let res = parse_template(r#"exec do "some stuff"   'with "quotes"' yeah"#);
         match res.unwrap() {
-            Template::Exec { command, args } => {
+            Template::Exec { command, args, .. } => {
                 assert_eq!(command, "do");
                 assert_eq!(args, vec!["some stuff", r#"with "quotes""#, "yeah"]);
             }
             _ => panic!("invliad template"),
         }
 
+        let res = parse_template(r#"exec sh -c "ls | wc -l" | replace x y | safe"#);
+        match res.unwrap() {
+            Template::Exec {
+                command,
+                args,
+                filters,
+                ..
+            } => {
+                assert_eq!(command, "sh");
+                assert_eq!(args, vec!["-c", "ls | wc -l"]);
+                assert_eq!(
+                    filters,
+                    vec![
+                        Filter {
+                            name: "replace",
+                            args: vec!["x", "y"]
+                        },
+                        Filter {
+                            name: "safe",
+                            args: vec![]
+                        }
+                    ]
+                );
+            }
+            _ => panic!("invliad template"),
+        }
+
+        let res = parse_template("exec pandoc -f rst:x | safe :");
+        match res.unwrap() {
+            Template::Exec {
+                args,
+                filters,
+                block,
+                ..
+            } => {
+                assert_eq!(args, vec!["-f", "rst:x"]);
+                assert_eq!(filters.len(), 1);
+                assert!(block);
+            }
+            _ => panic!("invliad template"),
+        }
+        assert!(matches!(
+            parse_template("exec echo:"),
+            Ok(Template::Exec {
+                command: "echo:",
+                block: false,
+                ..
+            })
+        ));
+
         assert!(matches!(
             parse_template(" exec  "),
             Err(Error::MissingArgument("command"))
         ));
+        assert!(matches!(
+            parse_template("exec :"),
+            Err(Error::MissingArgument("command"))
+        ));
+        for content in ["pagename :", "endexec :", "page.title :"] {
+            assert!(
+                matches!(parse_template(content), Err(Error::UnexpectedArgument(v)) if v == ":"),
+                "{content}"
+            );
+        }
+        assert!(matches!(
+            parse_template("exec ls |"),
+            Err(Error::MissingArgument("filter"))
+        ));
+    }
+
+    #[test]
+    fn exec_options() {
+        let res = parse_template("exec timeout=2.5 nocache perpage sleep 1");
+        match res.unwrap() {
+            Template::Exec {
+                options,
+                command,
+                args,
+                ..
+            } => {
+                assert_eq!(options.timeout, Some(Duration::from_millis(2500)));
+                assert!(options.nocache);
+                assert!(options.perpage);
+                assert_eq!(command, "sleep");
+                assert_eq!(args, vec!["1"]);
+            }
+            _ => panic!("invliad template"),
+        }
+
+        assert!(matches!(
+            parse_template("exec timeout=5"),
+            Err(Error::MissingArgument("command"))
+        ));
+        assert!(matches!(
+            parse_template("exec timeout=soon ls"),
+            Err(Error::InvalidExecOption(v)) if v == "timeout=soon"
+        ));
     }
 }
@@ -0,0 +1,272 @@
+use super::date::{source_date_epoch, Timezone};
+use super::errors::Result;
+use chrono::{DateTime, Locale, Utc};
+use figment::providers::{Env, Format, Toml};
+use figment::Figment;
+use glob::Pattern;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the site config file in the root of the source directory.
+pub const SITE_CONFIG_FILE: &str = "temple.toml";
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SiteConfig {
+    /// Maximum nesting depth of 'use' and 'extends' templates.
+    pub max_depth: usize,
+
+    /// Theme directories, relative to the source directory, which are
+    /// consulted in the given order after the site's own directories.
+    pub themes: Vec<PathBuf>,
+
+    /// Directory of the pages, relative to the source directory.
+    pub pages_dir: PathBuf,
+
+    /// Directory of the templates, relative to the source directory and to
+    /// each theme directory.
+    pub templates_dir: PathBuf,
+
+    /// Directory of the public files, relative to the source directory and to
+    /// each theme directory.
+    pub public_dir: PathBuf,
+
+    /// Directory of the data files, relative to the source directory.
+    pub data_dir: PathBuf,
+
+    /// Directory, relative to the output directory, into which the public
+    /// files are copied. An empty path copies them into the output directory
+    /// itself.
+    #[serde(deserialize_with = "deserialize_public_output")]
+    pub public_output: PathBuf,
+
+    /// Number of pages rendered in parallel. Defaults to the number of
+    /// available CPUs.
+    pub jobs: Option<usize>,
+
+    /// Directory, relative to the current working directory, in which build
+    /// results are cached across builds. Caching is disabled when not set.
+    pub cache_dir: Option<PathBuf>,
+
+    /// Indent the content included by 'use', 'call' and 'extends' like the
+    /// template including it.
+    pub indent_templates: bool,
+
+    /// Settings of 'exec' templates.
+    pub exec: ExecConfig,
+
+    /// Highlighting of code blocks at build time. Code blocks are not
+    /// highlighted when not set.
+    pub highlight: Option<HighlightConfig>,
+
+    /// Timezone in which dates are formatted. Defaults to the local timezone.
+    pub timezone: Timezone,
+
+    /// Locale in which names of months and weekdays are formatted, e.g.
+    /// `de_DE`. Defaults to English names.
+    #[serde(deserialize_with = "deserialize_locale")]
+    pub locale: Option<Locale>,
+
+    /// Source of the date returned by 'lastmodified'.
+    pub lastmodified: LastModifiedSource,
+
+    /// Date of the build used instead of the current date. Can only be set
+    /// via command line or the `SOURCE_DATE_EPOCH` environment variable.
+    #[serde(skip)]
+    pub date: Option<DateTime<Utc>>,
+
+    /// Replace the output directory even if it does not look like one created
+    /// by temple. Can only be set via command line.
+    #[serde(skip)]
+    pub force: bool,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 64,
+            themes: vec![],
+            pages_dir: "pages".into(),
+            templates_dir: "templates".into(),
+            public_dir: "public".into(),
+            data_dir: "data".into(),
+            public_output: "public".into(),
+            jobs: None,
+            cache_dir: None,
+            indent_templates: false,
+            exec: ExecConfig::default(),
+            highlight: None,
+            timezone: Timezone::default(),
+            locale: None,
+            lastmodified: LastModifiedSource::default(),
+            date: None,
+            force: false,
+        }
+    }
+}
+
+impl SiteConfig {
+    /// Loads the site config from the `temple.toml` file in the given source
+    /// directory, if present. Values can be overridden with environment
+    /// variables prefixed with `TEMPLE_`. The build date is taken from the
+    /// `SOURCE_DATE_EPOCH` environment variable, if set.
+    pub fn load(source_dir: impl AsRef<Path>) -> Result<Self> {
+        let mut config: Self = Figment::new()
+            .merge(Toml::file(source_dir.as_ref().join(SITE_CONFIG_FILE)))
+            .merge(Env::prefixed("TEMPLE_"))
+            .extract()
+            .map_err(Box::new)?;
+        config.date = source_date_epoch()?;
+        Ok(config)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LastModifiedSource {
+    /// The date of the last commit changing the page's source file. Falls
+    /// back to the file's modification time if the file is not committed.
+    #[default]
+    Git,
+    /// The modification time of the page's source file.
+    Mtime,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ExecConfig {
+    /// Whether 'exec' templates may run commands at all.
+    pub enabled: bool,
+
+    /// Glob patterns of the commands 'exec' templates may run. All commands
+    /// are allowed when not set.
+    #[serde(deserialize_with = "deserialize_patterns")]
+    pub allow: Option<Vec<Pattern>>,
+
+    /// Time in seconds after which commands are killed and the build fails.
+    /// Commands may run indefinitely when not set.
+    #[serde(deserialize_with = "deserialize_timeout")]
+    pub timeout: Option<Duration>,
+
+    /// Working directory of commands, relative to the source directory.
+    /// Defaults to the source directory itself.
+    pub workdir: Option<PathBuf>,
+
+    /// Additional environment variables passed to commands.
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allow: None,
+            timeout: None,
+            workdir: None,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HighlightConfig {
+    /// How highlighted code is styled.
+    pub style: HighlightStyle,
+
+    /// Name of the color theme, one of the themes bundled with syntect.
+    #[serde(deserialize_with = "deserialize_highlight_theme")]
+    pub theme: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            style: HighlightStyle::default(),
+            theme: "InspiredGitHub".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightStyle {
+    /// The colors of the theme are set with inline styles.
+    #[default]
+    Inline,
+    /// Code is annotated with CSS classes, which are styled by a stylesheet
+    /// for the theme written into the output.
+    Classes,
+}
+
+fn deserialize_timeout<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    let Some(secs) = Option::<f64>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Duration::try_from_secs_f64(secs)
+        .map(Some)
+        .map_err(|err| serde::de::Error::custom(format!("invalid timeout {secs}: {err}")))
+}
+
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<Pattern>>, D::Error> {
+    let Some(patterns) = Option::<Vec<String>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    patterns
+        .iter()
+        .map(|p| {
+            Pattern::new(p).map_err(|err| {
+                serde::de::Error::custom(format!("invalid command pattern '{p}': {err}"))
+            })
+        })
+        .collect::<std::result::Result<_, _>>()
+        .map(Some)
+}
+
+fn deserialize_locale<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Locale>, D::Error> {
+    let Some(locale) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Locale::try_from(locale.as_str())
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("unknown locale: {locale}")))
+}
+
+fn deserialize_highlight_theme<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    let theme = String::deserialize(deserializer)?;
+    let themes = syntect::highlighting::ThemeSet::load_defaults().themes;
+    if themes.contains_key(&theme) {
+        return Ok(theme);
+    }
+    let names: Vec<_> = themes.keys().map(|name| format!("'{name}'")).collect();
+    Err(serde::de::Error::custom(format!(
+        "unknown highlight theme '{theme}', expected one of {}",
+        names.join(", ")
+    )))
+}
+
+fn deserialize_public_output<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<PathBuf, D::Error> {
+    let path = PathBuf::deserialize(deserializer)?;
+    if !path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(serde::de::Error::custom(format!(
+            "public output must be a path within the output directory: {}",
+            path.display()
+        )));
+    }
+    Ok(path)
+}
@@ -0,0 +1,243 @@
+use super::filters::to_slug;
+use super::html::{attr_value, find_ignore_case, unescape_html};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Start and end of the markers for tables of contents, which are replaced
+/// once all headings of the page are known.
+const MARKER_START: char = '\u{E002}';
+const MARKER_END: char = '\u{E003}';
+
+/// A heading of a page.
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    pub level: u8,
+    pub id: String,
+    /// Content of the heading without tags.
+    pub text: String,
+}
+
+/// Returns the marker for a table of contents of the headings with levels
+/// between `min` and `max`, which is replaced by [`insert_tocs`].
+pub fn marker(min: u8, max: u8) -> String {
+    format!("{MARKER_START}{min},{max}{MARKER_END}")
+}
+
+/// Adds ids to all headings in `html` which do not have one, derived from
+/// their text, e.g. `id="getting-started"` for `<h2>Getting Started</h2>`.
+/// Returns the resulting HTML and all headings.
+pub fn anchor_headings(html: &str) -> (String, Vec<Heading>) {
+    let mut res = String::with_capacity(html.len());
+    let mut headings = vec![];
+    let mut ids = HashSet::new();
+
+    let mut rest = html;
+    while let Some((start, level)) = find_heading(rest) {
+        let open_end = match rest[start..].find('>') {
+            Some(i) => start + i,
+            None => break,
+        };
+        let close_tag = format!("</h{level}>");
+        let Some(close) = find_ignore_case(&rest[open_end..], &close_tag).map(|i| open_end + i)
+        else {
+            break;
+        };
+
+        let attrs = &rest[start + 3..open_end];
+        let inner = &rest[open_end + 1..close];
+        let text = strip_tags(inner);
+
+        let id = match attr_value(attrs, "id") {
+            Some(id) => {
+                res += &rest[..close];
+                id.to_string()
+            }
+            None => {
+                let id = unique_id(&to_slug(&unescape_html(&text)), &ids);
+                res += &rest[..open_end];
+                write!(res, r#" id="{id}""#).expect("write to string");
+                res += &rest[open_end..close];
+                id
+            }
+        };
+        ids.insert(id.clone());
+        headings.push(Heading {
+            level,
+            id,
+            text: text.trim().to_string(),
+        });
+        rest = &rest[close..];
+    }
+    res += rest;
+
+    (res, headings)
+}
+
+/// Replaces all markers in `html` with tables of contents of the given
+/// `headings`.
+pub fn insert_tocs(html: &str, headings: &[Heading]) -> String {
+    let mut res = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(MARKER_START) {
+        let args_start = start + MARKER_START.len_utf8();
+        let Some(len) = rest[args_start..].find(MARKER_END) else {
+            break;
+        };
+        let range = rest[args_start..args_start + len]
+            .split_once(',')
+            .and_then(|(min, max)| Some((min.parse().ok()?, max.parse().ok()?)));
+        res += &rest[..start];
+        if let Some((min, max)) = range {
+            res += &render_toc(headings, min, max);
+        }
+        rest = &rest[args_start + len + MARKER_END.len_utf8()..];
+    }
+    res += rest;
+    res
+}
+
+/// Renders the headings with levels between `min` and `max` into nested lists
+/// of links to them.
+fn render_toc(headings: &[Heading], min: u8, max: u8) -> String {
+    let mut res = String::new();
+    // Levels of the headings which started the currently open lists.
+    let mut open: Vec<u8> = vec![];
+
+    for h in headings.iter().filter(|h| (min..=max).contains(&h.level)) {
+        match open.last() {
+            None => {
+                res += "<ul class=\"toc\">\n";
+                open.push(h.level);
+            }
+            Some(&last) if h.level > last => {
+                res += "\n<ul>\n";
+                open.push(h.level);
+            }
+            Some(_) => {
+                res += "</li>\n";
+                while open.len() > 1 && open.last().is_some_and(|&l| l > h.level) {
+                    open.pop();
+                    res += "</ul>\n</li>\n";
+                }
+            }
+        }
+        write!(res, r##"<li><a href="#{}">{}</a>"##, h.id, h.text).expect("write to string");
+    }
+
+    while open.pop().is_some() {
+        res += "</li>\n</ul>\n";
+    }
+    res
+}
+
+/// Returns the position and level of the first heading tag in `html`.
+fn find_heading(html: &str) -> Option<(usize, u8)> {
+    let bytes = html.as_bytes();
+    let mut offset = 0;
+    while let Some(i) = html[offset..].find('<') {
+        let start = offset + i;
+        if let [b'h' | b'H', level @ b'1'..=b'6', next, ..] = bytes[start + 1..] {
+            if next == b'>' || next.is_ascii_whitespace() {
+                return Some((start, level - b'0'));
+            }
+        }
+        offset = start + 1;
+    }
+    None
+}
+
+/// Returns `html` without tags.
+fn strip_tags(html: &str) -> String {
+    let mut res = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => res.push(c),
+            _ => {}
+        }
+    }
+    res
+}
+
+/// Returns `slug`, followed by a number if it is already one of the `ids`.
+fn unique_id(slug: &str, ids: &HashSet<String>) -> String {
+    let slug = if slug.is_empty() { "heading" } else { slug };
+    if !ids.contains(slug) {
+        return slug.to_string();
+    }
+    (1..)
+        .map(|i| format!("{slug}-{i}"))
+        .find(|id| !ids.contains(id))
+        .expect("unused id")
+}
+
+#[cfg(test)]
+mod test_toc {
+    use super::*;
+
+    #[test]
+    fn anchors() {
+        let (html, headings) = anchor_headings(concat!(
+            "<h1>Intro &amp; Setup</h1>\n",
+            "<H2 class=\"x\">The <em>Basics</em></H2>\n",
+            "<h2 id=\"own\">Custom</h2>\n",
+            "<h2>The Basics</h2>\n",
+            "<header>no heading</header>",
+        ));
+        assert_eq!(
+            html,
+            concat!(
+                "<h1 id=\"intro-setup\">Intro &amp; Setup</h1>\n",
+                "<H2 class=\"x\" id=\"the-basics\">The <em>Basics</em></H2>\n",
+                "<h2 id=\"own\">Custom</h2>\n",
+                "<h2 id=\"the-basics-1\">The Basics</h2>\n",
+                "<header>no heading</header>",
+            )
+        );
+        let ids: Vec<_> = headings.iter().map(|h| (h.level, h.id.as_str())).collect();
+        assert_eq!(
+            ids,
+            [
+                (1, "intro-setup"),
+                (2, "the-basics"),
+                (2, "own"),
+                (2, "the-basics-1")
+            ]
+        );
+        assert_eq!(headings[1].text, "The Basics");
+    }
+
+    #[test]
+    fn tocs() {
+        let (_, headings) =
+            anchor_headings("<h1>A</h1><h2>B</h2><h3>C</h3><h2>D</h2><h4>E</h4><h1>F</h1>");
+        let html = format!("<nav>{}</nav>", marker(1, 6));
+        assert_eq!(
+            insert_tocs(&html, &headings),
+            concat!(
+                "<nav><ul class=\"toc\">\n",
+                "<li><a href=\"#a\">A</a>\n<ul>\n",
+                "<li><a href=\"#b\">B</a>\n<ul>\n",
+                "<li><a href=\"#c\">C</a></li>\n</ul>\n</li>\n",
+                "<li><a href=\"#d\">D</a>\n<ul>\n",
+                "<li><a href=\"#e\">E</a></li>\n</ul>\n</li>\n",
+                "</ul>\n</li>\n",
+                "<li><a href=\"#f\">F</a></li>\n",
+                "</ul>\n</nav>",
+            )
+        );
+
+        assert_eq!(
+            insert_tocs(&marker(2, 2), &headings),
+            concat!(
+                "<ul class=\"toc\">\n",
+                "<li><a href=\"#b\">B</a></li>\n",
+                "<li><a href=\"#d\">D</a></li>\n",
+                "</ul>\n",
+            )
+        );
+        assert_eq!(insert_tocs(&marker(5, 6), &headings), "");
+    }
+}
@@ -0,0 +1,78 @@
+use serde_json::{Map, Value};
+
+/// Converts a TOML value into a JSON value, so that values of all sources can
+/// be handled the same way. Dates and times are converted into strings.
+pub fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(v) => Value::String(v),
+        toml::Value::Integer(v) => Value::from(v),
+        toml::Value::Float(v) => Value::from(v),
+        toml::Value::Boolean(v) => Value::Bool(v),
+        toml::Value::Datetime(v) => Value::String(v.to_string()),
+        toml::Value::Array(v) => Value::Array(v.into_iter().map(from_toml).collect()),
+        toml::Value::Table(v) => Value::Object(
+            v.into_iter()
+                .map(|(k, v)| (k, from_toml(v)))
+                .collect::<Map<_, _>>(),
+        ),
+    }
+}
+
+/// Returns the value at the given dotted `path` within `root`, e.g.
+/// `page.title`. Array elements are addressed by their index, e.g.
+/// `page.tags.0`.
+pub fn lookup<'a>(root: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(root, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(list) => list.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// Returns the text inserted for a variable with the given value. Strings are
+/// inserted as they are, null as empty string and all other values as JSON.
+pub fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(v) => v.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test_value {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn toml_conversion() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            title = "hey"
+            date = 2024-05-01
+            tags = ["a", "b"]
+            meta = { draft = true, weight = 1.5 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            from_toml(toml::Value::Table(table)),
+            json!({
+                "title": "hey",
+                "date": "2024-05-01",
+                "tags": ["a", "b"],
+                "meta": { "draft": true, "weight": 1.5 },
+            })
+        );
+    }
+
+    #[test]
+    fn lookups() {
+        let root = json!({ "page": { "title": "hey", "tags": ["a", "b"] } });
+        assert_eq!(lookup(&root, "page.title"), Some(&json!("hey")));
+        assert_eq!(lookup(&root, "page.tags.1"), Some(&json!("b")));
+        assert_eq!(lookup(&root, "page.tags.2"), None);
+        assert_eq!(lookup(&root, "page.title.x"), None);
+        assert_eq!(lookup(&root, "nothing"), None);
+    }
+}